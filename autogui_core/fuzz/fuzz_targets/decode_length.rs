@@ -0,0 +1,17 @@
+//! `cargo fuzz run decode_length`: length prefixes and the messages behind them must decode without panicking.
+#![no_main]
+
+use autogui_core::codec;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Some(prefix) = data.first_chunk::<4>() {
+        if let Ok(length) = codec::decode_length(prefix) {
+            assert!(length <= codec::MAX_PAYLOAD);
+        }
+    }
+    if let Ok(Some((payload, used))) = codec::decode_message(data) {
+        assert_eq!(used, 4 + payload.len());
+        assert_eq!(&data[4..used], payload);
+    }
+});
@@ -0,0 +1,18 @@
+//! `cargo fuzz run decode_response`: replies from the server must decode without panicking, and whatever decodes
+//! must decode the same way again once encoded.
+#![no_main]
+
+use autogui_core::codec;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&with_payload, buf)) = data.split_first() else {
+        return;
+    };
+    let with_payload = with_payload & 1 == 1;
+    if let Ok(Some((response, used))) = codec::decode_response(buf, with_payload) {
+        assert!(used <= buf.len());
+        let encoded = codec::encode_response(&response).unwrap();
+        assert_eq!(codec::decode_response(&encoded, with_payload).unwrap(), Some((response, used)));
+    }
+});
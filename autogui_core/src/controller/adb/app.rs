@@ -0,0 +1,208 @@
+//! App lifecycle: launching, stopping and clearing packages, and finding the activity in the foreground.
+use std::fmt;
+
+use crate::error::{AGError, AGResult};
+
+use super::input::shell_quote;
+use super::package::{check_package, is_java_name, parse_pm_result};
+use super::ADB;
+
+/// A component as `am` and `dumpsys` print it, with the class name always fully qualified.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Activity {
+    pub package: String,
+    pub activity: String,
+}
+
+impl Activity {
+    /// Parses `package/class` or the short `package/.Class` form.
+    pub fn parse(component: &str) -> Option<Self> {
+        let (package, class) = component.split_once('/')?;
+        if !is_java_name(package, false) || !is_java_name(class, true) {
+            return None;
+        }
+        let activity = match class.strip_prefix('.') {
+            Some(_) => format!("{}{}", package, class),
+            None => class.to_string(),
+        };
+        Some(Activity {
+            package: package.to_string(),
+            activity,
+        })
+    }
+
+    /// Fails for fields set by hand to something that is not a package and class name.
+    pub(crate) fn check(&self) -> AGResult<()> {
+        check_package(&self.package)?;
+        if !is_java_name(&self.activity, true) {
+            return Err(AGError::InvalidName {
+                kind: "activity".to_string(),
+                name: self.activity.clone(),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Activity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.package, self.activity)
+    }
+}
+
+/// Keys naming the resumed or focused activity, from `dumpsys activity activities` (Android 12+ and older releases)
+/// and `dumpsys window`.
+const FOREGROUND_KEYS: [&str; 6] = [
+    "topResumedActivity=",
+    "ResumedActivity:",
+    "mResumedActivity:",
+    "mFocusedActivity:",
+    "mCurrentFocus=",
+    "mFocusedApp=",
+];
+
+/// First component on a line carrying one of `FOREGROUND_KEYS`, e.g.
+/// `mResumedActivity: ActivityRecord{5e1a0c u0 com.example/.MainActivity t42}`.
+pub(crate) fn parse_foreground(output: &str) -> Option<Activity> {
+    output
+        .lines()
+        .filter(|line| FOREGROUND_KEYS.iter().any(|key| line.trim_start().starts_with(key)))
+        .find_map(|line| line.split_whitespace().find_map(|token| Activity::parse(token.trim_end_matches('}'))))
+}
+
+impl ADB {
+    /// The resumed activity from `dumpsys activity`, falling back to the focused window from `dumpsys window`.
+    /// `None` when nothing app-owned has focus, e.g. on the lock screen.
+    pub fn foreground(&mut self) -> AGResult<Option<Activity>> {
+        let activities = self.shell_checked("dumpsys activity activities")?.stdout_str();
+        if let Some(activity) = parse_foreground(&activities) {
+            return Ok(Some(activity));
+        }
+        let windows = self.shell_checked("dumpsys window windows")?.stdout_str();
+        Ok(parse_foreground(&windows))
+    }
+
+    /// `am start -n`; a missing activity fails with `AGError::StartFailed` although `am` exits with 0.
+    pub fn start_activity(&mut self, activity: &Activity) -> AGResult<()> {
+        activity.check()?;
+        // Quoted so the shell leaves the `$` of inner classes alone.
+        let output = self.shell_checked(&format!("am start -n {}", shell_quote(&activity.to_string())))?;
+        let text = format!("{}{}", output.stdout_str(), output.stderr_str());
+        match text.lines().find(|l| l.starts_with("Error")) {
+            Some(error) => Err(AGError::StartFailed {
+                target: activity.to_string(),
+                message: error.trim_start_matches("Error:").trim().to_string(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Launches the package's launcher activity through `monkey`, without having to know its name.
+    pub fn start_app(&mut self, package: &str) -> AGResult<()> {
+        check_package(package)?;
+        let output = self.shell_output(&format!("monkey -p {} -c android.intent.category.LAUNCHER 1", package))?;
+        let text = format!("{}{}", output.stdout_str(), output.stderr_str());
+        if text.contains("No activities found") || text.contains("monkey aborted") {
+            return Err(AGError::StartFailed {
+                target: package.to_string(),
+                message: "no launcher activity".to_string(),
+            });
+        }
+        output.check("monkey")?;
+        Ok(())
+    }
+
+    pub fn force_stop(&mut self, package: &str) -> AGResult<()> {
+        check_package(package)?;
+        self.shell_checked(&format!("am force-stop {}", package))?;
+        Ok(())
+    }
+
+    /// `force_stop` followed by `start_app`.
+    pub fn restart_app(&mut self, package: &str) -> AGResult<()> {
+        self.force_stop(package)?;
+        self.start_app(package)
+    }
+
+    /// Deletes the package's data and cache with `pm clear`; this also stops it.
+    pub fn clear_data(&mut self, package: &str) -> AGResult<()> {
+        check_package(package)?;
+        let output = self.shell_output(&format!("pm clear {}", package))?;
+        parse_pm_result(&format!("{}{}", output.stdout_str(), output.stderr_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock::MockAdbServer;
+    use super::*;
+    use crate::controller::AdbBuilder;
+
+    #[test]
+    fn parse_components() {
+        let main = Activity::parse("com.example/.ui.MainActivity").unwrap();
+        assert_eq!(main.activity, "com.example.ui.MainActivity");
+        assert_eq!(main.to_string(), "com.example/com.example.ui.MainActivity");
+        assert_eq!(Activity::parse("u0"), None);
+        assert_eq!(Activity::parse("Window{1 u0 StatusBar}"), None);
+
+        let android12 = "  topResumedActivity=ActivityRecord{e7b1c2d u0 com.game/.Unity t31}\n";
+        assert_eq!(parse_foreground(android12).unwrap().package, "com.game");
+        let android9 = "    mResumedActivity: ActivityRecord{2f1 u0 com.android.launcher3/.Launcher t1}\n";
+        assert_eq!(parse_foreground(android9).unwrap().activity, "com.android.launcher3.Launcher");
+        let window =
+            "  mCurrentFocus=Window{9a u0 StatusBar}\n  mFocusedApp=AppWindowToken{7 token=Token{8 ActivityRecord{6 u0 com.x/com.x.Main t3}}}\n";
+        assert_eq!(parse_foreground(window).unwrap().activity, "com.x.Main");
+        assert_eq!(parse_foreground("  mCurrentFocus=null\n"), None);
+    }
+
+    #[test]
+    fn lifecycle_commands() {
+        let server = MockAdbServer::start()
+            .with_features(&["shell_v2"])
+            .with_reply("dumpsys activity activities", "nothing resumed\n")
+            .with_reply("dumpsys window windows", "  mCurrentFocus=Window{1 u0 com.game/com.game.Main}\n")
+            .with_reply(
+                "am start -n com.game/com.game.Missing",
+                "Error: Activity class {com.game/com.game.Missing} does not exist.\n",
+            )
+            .with_reply("am start -n com.game/com.game.Main", "Starting: Intent { cmp=com.game/.Main }\n")
+            .with_reply(
+                "am start -n 'com.game/com.game.Main$Inner'",
+                "Starting: Intent { cmp=com.game/.Main$Inner }\n",
+            )
+            .with_reply("monkey -p com.game -c android.intent.category.LAUNCHER 1", "Events injected: 1\n")
+            .with_shell_reply(
+                "monkey -p com.none -c android.intent.category.LAUNCHER 1",
+                "** No activities found to run, monkey aborted.\n",
+                "",
+                252,
+            )
+            .with_reply("pm clear com.game", "Success\n");
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).with_target("emulator-5554").build().unwrap();
+        let foreground = adb.foreground().unwrap().unwrap();
+        assert_eq!(foreground, Activity::parse("com.game/.Main").unwrap());
+        adb.start_activity(&foreground).unwrap();
+        adb.start_activity(&Activity::parse("com.game/.Main$Inner").unwrap()).unwrap();
+        assert!(server
+            .requests()
+            .contains(&"shell,v2,raw:am start -n 'com.game/com.game.Main$Inner'".to_string()));
+        let missing = Activity::parse("com.game/.Missing").unwrap();
+        assert!(matches!(adb.start_activity(&missing), Err(AGError::StartFailed { message, .. }) if message.contains("does not exist")));
+        adb.restart_app("com.game").unwrap();
+        assert!(matches!(adb.start_app("com.none"), Err(AGError::StartFailed { .. })));
+        adb.clear_data("com.game").unwrap();
+        assert!(server.requests().contains(&"shell,v2,raw:am force-stop com.game".to_string()));
+
+        let requests = server.requests().len();
+        let injected = Activity {
+            package: "com.game".to_string(),
+            activity: "Main; reboot".to_string(),
+        };
+        assert!(matches!(adb.start_activity(&injected), Err(AGError::InvalidName { kind, .. }) if kind == "activity"));
+        assert!(matches!(adb.start_app("com.game && reboot"), Err(AGError::InvalidName { .. })));
+        assert!(matches!(adb.force_stop("`reboot`"), Err(AGError::InvalidName { .. })));
+        assert!(matches!(adb.clear_data("a|b"), Err(AGError::InvalidName { .. })));
+        assert_eq!(server.requests().len(), requests);
+    }
+}
@@ -0,0 +1,173 @@
+//! Sans-IO encoder and decoder for the smart-socket wire format spoken by the adb server: requests and replies are
+//! prefixed with their length as 4 hex digits, and every request is answered with `OKAY` or `FAIL` + message.
+//! The `read_*` helpers at the bottom are the only part that touches a stream.
+use std::io::Read;
+
+use crate::error::{AGError, AGResult};
+
+/// Largest payload a 4-hex-digit length prefix can describe.
+pub const MAX_PAYLOAD: usize = 0xffff;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Okay,
+    Fail,
+}
+
+/// A complete reply: `OKAY` with an optional length-prefixed payload, or `FAIL` with its message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    Okay(Option<Vec<u8>>),
+    Fail(Vec<u8>),
+}
+
+pub fn encode_length(length: usize) -> AGResult<[u8; 4]> {
+    if length > MAX_PAYLOAD {
+        return Err(AGError::PayloadTooLarge(length));
+    }
+    let mut prefix = [0u8; 4];
+    hex::encode_to_slice((length as u16).to_be_bytes(), &mut prefix).map_err(|_| AGError::Decode)?;
+    prefix.make_ascii_uppercase();
+    Ok(prefix)
+}
+
+/// Length prefix followed by `payload`, the framing of both requests and host service replies.
+pub fn encode_message(payload: &[u8]) -> AGResult<Vec<u8>> {
+    let mut buffer = Vec::with_capacity(4 + payload.len());
+    buffer.extend_from_slice(&encode_length(payload.len())?);
+    buffer.extend_from_slice(payload);
+    Ok(buffer)
+}
+
+pub fn encode_response(response: &Response) -> AGResult<Vec<u8>> {
+    match response {
+        Response::Okay(None) => Ok(b"OKAY".to_vec()),
+        Response::Okay(Some(payload)) => Ok([b"OKAY".as_slice(), &encode_message(payload)?].concat()),
+        Response::Fail(message) => Ok([b"FAIL".as_slice(), &encode_message(message)?].concat()),
+    }
+}
+
+/// Accepts upper- and lower-case hex digits, as both show up in the wild.
+pub fn decode_length(prefix: &[u8; 4]) -> AGResult<usize> {
+    let mut length = [0u8; 2];
+    hex::decode_to_slice(prefix, &mut length).map_err(|_| AGError::Decode)?;
+    Ok(u16::from_be_bytes(length) as usize)
+}
+
+pub fn decode_status(status: &[u8; 4]) -> AGResult<Status> {
+    match status {
+        b"OKAY" => Ok(Status::Okay),
+        b"FAIL" => Ok(Status::Fail),
+        _ => Err(AGError::Decode),
+    }
+}
+
+/// Decodes one length-prefixed message from the front of `buf`, returning it with the number of bytes consumed,
+/// or `None` if `buf` does not hold all of it yet.
+pub fn decode_message(buf: &[u8]) -> AGResult<Option<(&[u8], usize)>> {
+    let Some(prefix) = buf.first_chunk::<4>() else {
+        return Ok(None);
+    };
+    let length = decode_length(prefix)?;
+    Ok(buf.get(4..4 + length).map(|payload| (payload, 4 + length)))
+}
+
+/// Decodes a reply from the front of `buf`. `with_payload` says whether an `OKAY` is followed by a message, which
+/// depends on the service that was requested.
+pub fn decode_response(buf: &[u8], with_payload: bool) -> AGResult<Option<(Response, usize)>> {
+    let Some(status) = buf.first_chunk::<4>() else {
+        return Ok(None);
+    };
+    let status = decode_status(status)?;
+    if status == Status::Okay && !with_payload {
+        return Ok(Some((Response::Okay(None), 4)));
+    }
+    let Some((payload, used)) = decode_message(&buf[4..])? else {
+        return Ok(None);
+    };
+    let response = match status {
+        Status::Okay => Response::Okay(Some(payload.to_vec())),
+        Status::Fail => Response::Fail(payload.to_vec()),
+    };
+    Ok(Some((response, 4 + used)))
+}
+
+pub fn read_status(reader: &mut impl Read) -> AGResult<Status> {
+    let mut status = [0u8; 4];
+    reader.read_exact(&mut status)?;
+    decode_status(&status)
+}
+
+pub fn read_message(reader: &mut impl Read) -> AGResult<Vec<u8>> {
+    let mut prefix = [0u8; 4];
+    reader.read_exact(&mut prefix)?;
+    let mut payload = vec![0u8; decode_length(&prefix)?];
+    reader.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn response() -> impl Strategy<Value = (Response, bool)> {
+        let payload = || proptest::collection::vec(any::<u8>(), 0..512);
+        prop_oneof![
+            Just((Response::Okay(None), false)),
+            payload().prop_map(|p| (Response::Okay(Some(p)), true)),
+            (payload(), any::<bool>()).prop_map(|(p, with_payload)| (Response::Fail(p), with_payload)),
+        ]
+    }
+
+    #[test]
+    fn known_frames() {
+        assert_eq!(encode_message(b"host:version").unwrap(), b"000Chost:version");
+        assert_eq!(decode_length(b"fFfF").unwrap(), 0xffff);
+        assert!(decode_length(b"00x1").is_err());
+        assert!(decode_status(b"OKAX").is_err());
+        let reply = b"FAIL0010device not found";
+        let expected = Response::Fail(b"device not found".to_vec());
+        assert_eq!(decode_response(reply, false).unwrap(), Some((expected, reply.len())));
+    }
+
+    #[test]
+    fn oversized_payload_is_rejected() {
+        assert!(encode_message(&vec![b'a'; MAX_PAYLOAD]).is_ok());
+        assert!(matches!(
+            encode_message(&vec![b'a'; MAX_PAYLOAD + 1]),
+            Err(AGError::PayloadTooLarge(65536))
+        ));
+    }
+
+    proptest! {
+        #[test]
+        fn message_roundtrip(payload in proptest::collection::vec(any::<u8>(), 0..2048), trailing in proptest::collection::vec(any::<u8>(), 0..8)) {
+            let mut encoded = encode_message(&payload).unwrap();
+            let used = encoded.len();
+            encoded.extend_from_slice(&trailing);
+            prop_assert_eq!(decode_message(&encoded).unwrap(), Some((payload.as_slice(), used)));
+            prop_assert_eq!(read_message(&mut encoded.as_slice()).unwrap(), payload);
+        }
+
+        #[test]
+        fn response_roundtrip((response, with_payload) in response()) {
+            let encoded = encode_response(&response).unwrap();
+            prop_assert_eq!(decode_response(&encoded, with_payload).unwrap(), Some((response, encoded.len())));
+            for cut in 0..encoded.len() {
+                prop_assert_eq!(decode_response(&encoded[..cut], with_payload).unwrap(), None);
+            }
+        }
+
+        #[test]
+        fn arbitrary_input_never_panics(data in proptest::collection::vec(any::<u8>(), 0..64), with_payload in any::<bool>()) {
+            if let Ok(Some((_, used))) = decode_response(&data, with_payload) {
+                prop_assert!(used <= data.len());
+            }
+            if let Ok(Some((payload, used))) = decode_message(&data) {
+                prop_assert_eq!(payload.len() + 4, used);
+            }
+            let _ = read_message(&mut data.as_slice());
+        }
+    }
+}
@@ -0,0 +1,181 @@
+//! Device enumeration via `host:devices-l`.
+use crate::error::{AGError, AGResult};
+
+use super::ADB;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceState {
+    Device,
+    Offline,
+    Unauthorized,
+    /// Any other state reported by the server, e.g. `recovery`, `bootloader` or `no permissions`.
+    Other(String),
+}
+
+impl DeviceState {
+    fn parse(s: &str) -> Self {
+        match s {
+            "device" => DeviceState::Device,
+            "offline" => DeviceState::Offline,
+            "unauthorized" => DeviceState::Unauthorized,
+            other => DeviceState::Other(other.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub serial: String,
+    pub state: DeviceState,
+    pub product: Option<String>,
+    pub model: Option<String>,
+    pub device: Option<String>,
+    pub transport_id: Option<u64>,
+}
+
+impl DeviceInfo {
+    /// Parses one line of `host:devices-l`, e.g.
+    /// `emulator-5554 device product:sdk_gphone_x86 model:Pixel_5 device:generic_x86 transport_id:1`.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split_whitespace();
+        let serial = fields.next()?.to_string();
+        let mut state = Vec::new();
+        let mut info = DeviceInfo {
+            serial,
+            state: DeviceState::Offline,
+            product: None,
+            model: None,
+            device: None,
+            transport_id: None,
+        };
+        for field in fields {
+            match field.split_once(':') {
+                Some(("product", v)) => info.product = Some(v.to_string()),
+                Some(("model", v)) => info.model = Some(v.to_string()),
+                Some(("device", v)) => info.device = Some(v.to_string()),
+                Some(("transport_id", v)) => info.transport_id = v.parse().ok(),
+                Some(("usb", _)) => {}
+                _ => state.push(field),
+            }
+        }
+        if state.is_empty() {
+            return None;
+        }
+        info.state = DeviceState::parse(&state.join(" "));
+        Some(info)
+    }
+
+    pub fn parse_list(data: &str) -> Vec<Self> {
+        data.lines().filter_map(Self::parse).collect()
+    }
+}
+
+/// How `AdbBuilder` picks the device to talk to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceSelector {
+    Serial(String),
+    /// Matches `model:` from `host:devices-l`, where the server has replaced spaces with underscores.
+    Model(String),
+    /// The single device in the `device` state; fails if there are none or several.
+    Only,
+}
+
+impl DeviceSelector {
+    pub fn select<'a>(&self, devices: &'a [DeviceInfo]) -> AGResult<&'a DeviceInfo> {
+        let mut ready = devices.iter().filter(|d| d.state == DeviceState::Device);
+        match self {
+            DeviceSelector::Serial(serial) => ready
+                .find(|d| &d.serial == serial)
+                .ok_or_else(|| AGError::DeviceNotFound { serial: serial.clone() }),
+            DeviceSelector::Model(model) => {
+                let model = model.replace(' ', "_");
+                ready
+                    .find(|d| d.model.as_deref() == Some(model.as_str()))
+                    .ok_or(AGError::NoDevice { model: Some(model) })
+            }
+            DeviceSelector::Only => match (ready.next(), ready.next()) {
+                (Some(device), None) => Ok(device),
+                (None, _) => Err(AGError::NoDevice { model: None }),
+                (Some(first), Some(second)) => {
+                    let serials = [first, second].into_iter().chain(ready).map(|d| d.serial.clone()).collect();
+                    Err(AGError::MultipleDevices { serials })
+                }
+            },
+        }
+    }
+}
+
+impl ADB {
+    /// Lists the devices known to the ADB server.
+    pub fn devices(&mut self) -> AGResult<Vec<DeviceInfo>> {
+        let data = self.host_request("host:devices-l")?;
+        Ok(DeviceInfo::parse_list(&data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock::MockAdbServer;
+    use super::*;
+    use crate::controller::AdbBuilder;
+
+    #[test]
+    fn parse_devices_l() {
+        let data = "emulator-5554          device product:sdk_gphone_x86 model:Pixel_5 device:generic_x86 transport_id:1\n\
+                    0123456789ABCDEF       unauthorized usb:1-1 transport_id:2\n\
+                    127.0.0.1:7555         offline transport_id:3\n\
+                    ZX1G22                 no permissions (user in plugdev group) usb:1-2 transport_id:4\n";
+        let devices = DeviceInfo::parse_list(data);
+        assert_eq!(devices.len(), 4);
+        assert_eq!(
+            devices[0],
+            DeviceInfo {
+                serial: "emulator-5554".to_string(),
+                state: DeviceState::Device,
+                product: Some("sdk_gphone_x86".to_string()),
+                model: Some("Pixel_5".to_string()),
+                device: Some("generic_x86".to_string()),
+                transport_id: Some(1),
+            }
+        );
+        assert_eq!(devices[1].state, DeviceState::Unauthorized);
+        assert_eq!(devices[2].state, DeviceState::Offline);
+        assert_eq!(devices[3].state, DeviceState::Other("no permissions (user in plugdev group)".to_string()));
+        assert_eq!(devices[3].transport_id, Some(4));
+    }
+
+    #[test]
+    fn builder_selects_device() {
+        let server = MockAdbServer::start()
+            .with_device_info("emulator-5554", "device", "Pixel_5")
+            .with_device_info("emulator-5556", "offline", "Pixel_6")
+            .with_device_info("127.0.0.1:7555", "device", "MuMu");
+        let adb = AdbBuilder::new().with_addr(server.addr()).with_model("Pixel 5").build().unwrap();
+        assert_eq!(adb.target, "emulator-5554");
+        let adb = AdbBuilder::new().with_addr(server.addr()).with_serial("127.0.0.1:7555").build().unwrap();
+        assert_eq!(adb.target, "127.0.0.1:7555");
+        assert!(matches!(
+            AdbBuilder::new().with_addr(server.addr()).with_serial("emulator-5556").build(),
+            Err(AGError::DeviceNotFound { .. })
+        ));
+        assert!(matches!(
+            AdbBuilder::new().with_addr(server.addr()).with_model("Pixel 6").build(),
+            Err(AGError::NoDevice { model: Some(model) }) if model == "Pixel_6"
+        ));
+        match AdbBuilder::new().with_addr(server.addr()).with_only_device().build() {
+            Err(AGError::MultipleDevices { serials }) => assert_eq!(serials, ["emulator-5554", "127.0.0.1:7555"]),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(DeviceSelector::Only.select(&[]), Err(AGError::NoDevice { model: None })));
+    }
+
+    #[test]
+    fn builder_selects_only_device() {
+        let server = MockAdbServer::start()
+            .with_device_info("emulator-5554", "device", "Pixel_5")
+            .with_device_info("ABC", "unauthorized", "");
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).with_only_device().build().unwrap();
+        assert_eq!(adb.target, "emulator-5554");
+        assert_eq!(adb.devices().unwrap().len(), 2);
+    }
+}
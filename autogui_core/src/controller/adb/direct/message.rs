@@ -0,0 +1,119 @@
+//! Packets of the device-side ADB protocol: a 24-byte little-endian header followed by the payload.
+use std::io::{Read, Write};
+
+use crate::error::{AGError, AGResult};
+
+pub const A_CNXN: u32 = 0x4e584e43;
+pub const A_AUTH: u32 = 0x48545541;
+pub const A_OPEN: u32 = 0x4e45504f;
+pub const A_OKAY: u32 = 0x59414b4f;
+pub const A_CLSE: u32 = 0x45534c43;
+pub const A_WRTE: u32 = 0x45545257;
+
+/// Protocol version that still carries payload checksums; adbd accepts it from every release.
+pub const A_VERSION: u32 = 0x01000000;
+/// From this version on the checksum is ignored by both sides.
+pub const A_VERSION_SKIP_CHECKSUM: u32 = 0x01000001;
+pub const MAX_PAYLOAD: u32 = 256 * 1024;
+
+pub const AUTH_TOKEN: u32 = 1;
+pub const AUTH_SIGNATURE: u32 = 2;
+pub const AUTH_RSAPUBLICKEY: u32 = 3;
+
+const HEADER_LEN: usize = 24;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub command: u32,
+    pub arg0: u32,
+    pub arg1: u32,
+    pub payload: Vec<u8>,
+}
+
+fn checksum(payload: &[u8]) -> u32 {
+    payload.iter().map(|&b| b as u32).fold(0, u32::wrapping_add)
+}
+
+impl Message {
+    pub fn new(command: u32, arg0: u32, arg1: u32, payload: impl Into<Vec<u8>>) -> Self {
+        Message {
+            command,
+            arg0,
+            arg1,
+            payload: payload.into(),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let header = [
+            self.command,
+            self.arg0,
+            self.arg1,
+            self.payload.len() as u32,
+            checksum(&self.payload),
+            !self.command,
+        ];
+        let mut buffer: Vec<u8> = header.iter().flat_map(|v| v.to_le_bytes()).collect();
+        buffer.extend_from_slice(&self.payload);
+        buffer
+    }
+
+    /// Decodes one packet from the front of `buf`, or `None` if it is not complete yet. `check` enables the payload
+    /// checksum, which only peers older than `A_VERSION_SKIP_CHECKSUM` fill in.
+    pub fn decode(buf: &[u8], check: bool) -> AGResult<Option<(Self, usize)>> {
+        let Some(header) = buf.first_chunk::<HEADER_LEN>() else {
+            return Ok(None);
+        };
+        let field = |i: usize| u32::from_le_bytes([header[i * 4], header[i * 4 + 1], header[i * 4 + 2], header[i * 4 + 3]]);
+        let (command, length, sum, magic) = (field(0), field(3) as usize, field(4), field(5));
+        if magic != !command || length > MAX_PAYLOAD as usize {
+            return Err(AGError::Decode);
+        }
+        let Some(payload) = buf.get(HEADER_LEN..HEADER_LEN + length) else {
+            return Ok(None);
+        };
+        if check && checksum(payload) != sum {
+            return Err(AGError::Decode);
+        }
+        Ok(Some((Message::new(command, field(1), field(2), payload), HEADER_LEN + length)))
+    }
+
+    pub fn read(reader: &mut impl Read, check: bool) -> AGResult<Self> {
+        let mut buf = vec![0u8; HEADER_LEN];
+        reader.read_exact(&mut buf)?;
+        let length = u32::from_le_bytes([buf[12], buf[13], buf[14], buf[15]]) as usize;
+        if length > MAX_PAYLOAD as usize {
+            return Err(AGError::Decode);
+        }
+        buf.resize(HEADER_LEN + length, 0);
+        reader.read_exact(&mut buf[HEADER_LEN..])?;
+        Message::decode(&buf, check)?.map(|(message, _)| message).ok_or(AGError::Decode)
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> AGResult<()> {
+        writer.write_all(&self.encode())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip_and_validation() {
+        let message = Message::new(A_OPEN, 1, 0, b"shell:ls\0".to_vec());
+        let encoded = message.encode();
+        assert_eq!(&encoded[..4], b"OPEN");
+        assert_eq!(Message::decode(&encoded, true).unwrap(), Some((message.clone(), encoded.len())));
+        assert_eq!(Message::decode(&encoded[..30], true).unwrap(), None);
+        assert_eq!(Message::read(&mut encoded.as_slice(), true).unwrap(), message);
+
+        let mut corrupted = encoded.clone();
+        corrupted[24] ^= 1;
+        assert!(Message::decode(&corrupted, true).is_err());
+        assert!(Message::decode(&corrupted, false).is_ok());
+        corrupted[20] ^= 1;
+        assert!(Message::decode(&corrupted, false).is_err());
+    }
+}
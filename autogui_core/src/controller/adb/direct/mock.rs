@@ -0,0 +1,181 @@
+//! In-process stand-in for adbd: runs the `AUTH` handshake with real signature checks and answers `OPEN` streams.
+use std::collections::HashMap;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use rsa::RsaPublicKey;
+
+use super::auth::{decode_public_key, verify};
+use super::message::*;
+
+const BANNER: &[u8] = b"device::ro.product.name=mock;ro.product.model=Mock adbd;ro.product.device=mock;features=shell_v2,cmd;";
+
+#[derive(Debug, Clone)]
+struct Reply {
+    output: Vec<u8>,
+    endless: bool,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    replies: HashMap<String, Reply>,
+    trusted: Vec<RsaPublicKey>,
+    auth_steps: Vec<String>,
+    services: Vec<String>,
+}
+
+pub(crate) struct MockAdbd {
+    addr: String,
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockAdbd {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock adbd");
+        let addr = listener.local_addr().unwrap().to_string();
+        let state = Arc::new(Mutex::new(MockState::default()));
+        let shared = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let state = shared.clone();
+                thread::spawn(move || handle_connection(stream, &state));
+            }
+        });
+        MockAdbd { addr, state }
+    }
+
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    pub fn with_reply(self, cmd: &str, output: &str) -> Self {
+        let reply = Reply {
+            output: output.as_bytes().to_vec(),
+            endless: false,
+        };
+        self.state.lock().unwrap().replies.insert(cmd.to_string(), reply);
+        self
+    }
+
+    /// Repeats `line` until the host closes the stream.
+    pub fn with_endless_reply(self, cmd: &str, line: &str) -> Self {
+        let reply = Reply {
+            output: line.as_bytes().to_vec(),
+            endless: true,
+        };
+        self.state.lock().unwrap().replies.insert(cmd.to_string(), reply);
+        self
+    }
+
+    pub fn auth_steps(&self) -> Vec<String> {
+        self.state.lock().unwrap().auth_steps.clone()
+    }
+
+    pub fn services(&self) -> Vec<String> {
+        self.state.lock().unwrap().services.clone()
+    }
+}
+
+type Writer = Arc<Mutex<TcpStream>>;
+
+fn send(writer: &Writer, message: Message) -> bool {
+    message.write(&mut *writer.lock().unwrap()).is_ok()
+}
+
+/// Returns once the host is authenticated, or `None` if it gave up.
+fn authenticate(stream: &mut TcpStream, state: &Mutex<MockState>) -> Option<()> {
+    let connect = Message::read(stream, true).ok()?;
+    assert_eq!(connect.command, A_CNXN);
+    let token = [0x5a; 20];
+    Message::new(A_AUTH, AUTH_TOKEN, 0, token.to_vec()).write(stream).ok()?;
+    loop {
+        let message = Message::read(stream, true).ok()?;
+        assert_eq!(message.command, A_AUTH);
+        let mut state = state.lock().unwrap();
+        let step = if message.arg0 == AUTH_SIGNATURE {
+            if state.trusted.iter().any(|key| verify(key, &token, &message.payload)) {
+                "signature accepted"
+            } else {
+                "signature rejected"
+            }
+        } else {
+            let key = decode_public_key(&message.payload).expect("valid public key");
+            state.trusted.push(key);
+            "public key accepted"
+        };
+        state.auth_steps.push(step.to_string());
+        if step == "signature rejected" {
+            Message::new(A_AUTH, AUTH_TOKEN, 0, token.to_vec()).write(stream).ok()?;
+        } else {
+            return Message::new(A_CNXN, A_VERSION_SKIP_CHECKSUM, MAX_PAYLOAD, BANNER.to_vec())
+                .write(stream)
+                .ok();
+        }
+    }
+}
+
+fn handle_connection(mut stream: TcpStream, state: &Mutex<MockState>) {
+    if authenticate(&mut stream, state).is_none() {
+        return;
+    }
+    let writer: Writer = Arc::new(Mutex::new(stream.try_clone().unwrap()));
+    let mut acks: HashMap<u32, Sender<()>> = HashMap::new();
+    let mut next_id = 100;
+    while let Ok(message) = Message::read(&mut stream, false) {
+        let (remote, local) = (message.arg0, message.arg1);
+        match message.command {
+            A_OPEN => {
+                let service = String::from_utf8_lossy(&message.payload).trim_end_matches('\0').to_string();
+                state.lock().unwrap().services.push(service.clone());
+                let (v2, cmd) = match service.split_once(':') {
+                    Some(("shell,v2,raw", cmd)) => (true, cmd),
+                    Some(("shell" | "exec", cmd)) => (false, cmd),
+                    _ => (false, ""),
+                };
+                let Some(reply) = state.lock().unwrap().replies.get(cmd).cloned() else {
+                    send(&writer, Message::new(A_CLSE, 0, remote, Vec::new()));
+                    continue;
+                };
+                next_id += 1;
+                let (tx, rx) = mpsc::channel();
+                acks.insert(next_id, tx);
+                send(&writer, Message::new(A_OKAY, next_id, remote, Vec::new()));
+                let (writer, local) = (writer.clone(), next_id);
+                thread::spawn(move || write_reply(&writer, local, remote, reply, v2, &rx));
+            }
+            A_WRTE => {
+                send(&writer, Message::new(A_OKAY, local, remote, Vec::new()));
+            }
+            A_OKAY => {
+                if let Some(ack) = acks.get(&local) {
+                    let _ = ack.send(());
+                }
+            }
+            A_CLSE if acks.remove(&local).is_some() => {
+                send(&writer, Message::new(A_CLSE, local, remote, Vec::new()));
+            }
+            _ => {}
+        }
+    }
+}
+
+fn write_reply(writer: &Writer, local: u32, remote: u32, reply: Reply, v2: bool, acks: &Receiver<()>) {
+    let write = |payload: Vec<u8>| send(writer, Message::new(A_WRTE, local, remote, payload)) && acks.recv().is_ok();
+    if reply.endless {
+        while write(reply.output.clone()) {}
+        return;
+    }
+    let chunks = if v2 {
+        let mut stdout = vec![1];
+        stdout.extend_from_slice(&(reply.output.len() as u32).to_le_bytes());
+        stdout.extend_from_slice(&reply.output);
+        vec![stdout, vec![3, 1, 0, 0, 0, 0]]
+    } else {
+        vec![reply.output]
+    };
+    if chunks.into_iter().all(write) {
+        send(writer, Message::new(A_CLSE, local, remote, Vec::new()));
+    }
+}
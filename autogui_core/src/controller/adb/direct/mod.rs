@@ -0,0 +1,429 @@
+//! Talks to adbd over TCP without a host ADB server. A loopback bridge answers the smart-socket protocol that `ADB`
+//! speaks and multiplexes every device service onto the single adbd connection as an OPEN/WRTE/CLSE stream, so all
+//! `ADB` methods work unchanged on top of it.
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::error::{AGError, AGResult};
+
+use super::codec::{self, Response};
+use message::*;
+
+mod auth;
+mod message;
+#[cfg(test)]
+pub(crate) mod mock;
+pub use auth::AdbKey;
+
+/// How long the user gets to accept the "Allow USB debugging?" prompt for a new key.
+const AUTH_PROMPT_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Connection {
+    /// Through the host ADB server, started with `adb start-server` if it is not running.
+    #[default]
+    Server,
+    /// Straight to the adbd listening on the target address, e.g. an emulator's `127.0.0.1:5555`.
+    Direct,
+}
+
+enum Event {
+    Okay(u32),
+    Write(Vec<u8>),
+    Close,
+}
+
+/// The `device::key=value;...` banner adbd sends with its `CNXN`.
+fn parse_banner(banner: &[u8]) -> HashMap<String, String> {
+    let banner = String::from_utf8_lossy(banner);
+    let properties = banner.split_once("::").map(|(_, p)| p).unwrap_or_default();
+    properties
+        .trim_end_matches('\0')
+        .split(';')
+        .filter_map(|p| p.split_once('='))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+fn handshake(stream: &mut TcpStream, key: &AdbKey, target: &str) -> AGResult<Message> {
+    Message::new(A_CNXN, A_VERSION, MAX_PAYLOAD, b"host::\0".to_vec()).write(stream)?;
+    let (mut signed, mut offered) = (false, false);
+    loop {
+        let message = match Message::read(stream, false).map_err(|e| e.in_service(target, "CNXN")) {
+            // Only a device that rejected our signature and then left the key prompt unanswered is unauthorized.
+            Err(AGError::Timeout { serial, .. }) if offered => return Err(AGError::DeviceUnauthorized { serial }),
+            message => message?,
+        };
+        match (message.command, message.arg0) {
+            (A_CNXN, _) => return Ok(message),
+            (A_AUTH, AUTH_TOKEN) if !signed => {
+                signed = true;
+                Message::new(A_AUTH, AUTH_SIGNATURE, 0, key.sign(&message.payload)?).write(stream)?;
+            }
+            (A_AUTH, AUTH_TOKEN) if !offered => {
+                offered = true;
+                Message::new(A_AUTH, AUTH_RSAPUBLICKEY, 0, key.public_key()).write(stream)?;
+                stream.set_read_timeout(Some(AUTH_PROMPT_TIMEOUT))?;
+            }
+            _ => return Err(AGError::Decode),
+        }
+    }
+}
+
+struct Device {
+    serial: String,
+    writer: Mutex<TcpStream>,
+    routes: Mutex<HashMap<u32, Sender<Event>>>,
+    next_id: AtomicU32,
+    properties: HashMap<String, String>,
+    max_payload: usize,
+    /// Older adbd fills in payload checksums and expects them to be checked.
+    checksum: bool,
+    timeout: Duration,
+}
+
+impl Device {
+    fn send(&self, message: Message) -> AGResult<()> {
+        message.write(&mut *self.writer.lock().unwrap())
+    }
+
+    fn features(&self) -> String {
+        self.properties.get("features").cloned().unwrap_or_default()
+    }
+
+    fn listing(&self, long: bool) -> String {
+        if !long {
+            return format!("{}\tdevice\n", self.serial);
+        }
+        let property = |name: &str| self.properties.get(name).map(|v| v.as_str()).unwrap_or("unknown");
+        format!(
+            "{} device product:{} model:{} device:{} transport_id:1\n",
+            self.serial,
+            property("ro.product.name"),
+            property("ro.product.model").replace(' ', "_"),
+            property("ro.product.device")
+        )
+    }
+
+    /// Routes packets from adbd to the stream they belong to until the connection drops.
+    fn read_loop(&self, mut reader: TcpStream) {
+        while let Ok(message) = Message::read(&mut reader, self.checksum) {
+            let mut routes = self.routes.lock().unwrap();
+            let event = match message.command {
+                A_OKAY => Event::Okay(message.arg0),
+                A_WRTE => Event::Write(message.payload),
+                A_CLSE => Event::Close,
+                _ => continue,
+            };
+            let closing = matches!(event, Event::Close);
+            if let Some(route) = routes.get(&message.arg1) {
+                let _ = route.send(event);
+            }
+            if closing {
+                routes.remove(&message.arg1);
+            }
+        }
+        self.routes.lock().unwrap().clear();
+    }
+
+    fn open(&self, service: &str) -> AGResult<(u32, u32, Receiver<Event>)> {
+        let local = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = mpsc::channel();
+        self.routes.lock().unwrap().insert(local, tx);
+        self.send(Message::new(A_OPEN, local, 0, format!("{}\0", service)))?;
+        match rx.recv_timeout(self.timeout) {
+            Ok(Event::Okay(remote)) => Ok((local, remote, rx)),
+            _ => {
+                self.routes.lock().unwrap().remove(&local);
+                Err(AGError::AdbFail {
+                    serial: self.serial.clone(),
+                    service: service.to_string(),
+                    message: "closed".to_string(),
+                })
+            }
+        }
+    }
+
+    /// Copies between the smart-socket client and an opened adbd stream until either side closes.
+    fn pump(self: &Arc<Self>, mut client: TcpStream, local: u32, remote: u32, events: Receiver<Event>) -> AGResult<()> {
+        let (ack_tx, ack_rx) = mpsc::channel();
+        let mut client_writer = client.try_clone()?;
+        let device = self.clone();
+        thread::spawn(move || {
+            for event in events {
+                match event {
+                    Event::Okay(_) => {
+                        let _ = ack_tx.send(());
+                    }
+                    Event::Write(data) => {
+                        if client_writer.write_all(&data).is_err() || device.send(Message::new(A_OKAY, local, remote, Vec::new())).is_err() {
+                            break;
+                        }
+                    }
+                    Event::Close => break,
+                }
+            }
+            // Only half-close: the client may still be writing, and closing a socket with unread data resets it.
+            let _ = client_writer.shutdown(Shutdown::Write);
+        });
+        let mut buf = vec![0u8; self.max_payload];
+        let mut open = true;
+        loop {
+            let n = match client.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            // adbd acknowledges every WRTE and expects no other one in flight until it has. Once the device side is
+            // gone, the rest is drained until the client hangs up.
+            if open {
+                open = self.send(Message::new(A_WRTE, local, remote, &buf[..n])).is_ok() && ack_rx.recv().is_ok();
+            }
+        }
+        if self.routes.lock().unwrap().remove(&local).is_some() {
+            self.send(Message::new(A_CLSE, local, remote, Vec::new()))?;
+        }
+        Ok(())
+    }
+
+    /// Serves one smart-socket connection: host services are answered locally, the first request after a
+    /// `host:transport` is opened on the device.
+    fn serve(self: &Arc<Self>, mut client: TcpStream) -> AGResult<()> {
+        let mut transport = false;
+        loop {
+            let request = String::from_utf8_lossy(&codec::read_message(&mut client)?).to_string();
+            let okay = |payload: &str| Response::Okay(Some(payload.as_bytes().to_vec()));
+            let response = if transport {
+                match self.open(&request) {
+                    Ok((local, remote, events)) => {
+                        client.write_all(&codec::encode_response(&Response::Okay(None))?)?;
+                        return self.pump(client, local, remote, events);
+                    }
+                    Err(_) => Response::Fail(b"closed".to_vec()),
+                }
+            } else if let Some(serial) = request.strip_prefix("host:transport:") {
+                if serial != self.serial {
+                    Response::Fail(format!("device '{}' not found", serial).into_bytes())
+                } else {
+                    transport = true;
+                    Response::Okay(None)
+                }
+            } else if request == "host:transport-any" {
+                transport = true;
+                Response::Okay(None)
+            } else if request == "host:features" || request == format!("host-serial:{}:features", self.serial) {
+                okay(&self.features())
+            } else if request == "host:devices" || request == "host:devices-l" {
+                okay(&self.listing(request.ends_with("-l")))
+            } else if request == "host:track-devices" {
+                client.write_all(&codec::encode_response(&okay(&self.listing(false)))?)?;
+                // The device list never changes on a direct connection; hold the socket until the tracker goes away.
+                let _ = client.read(&mut [0u8; 1]);
+                return Ok(());
+            } else if let Some(target) = request.strip_prefix("host:connect:") {
+                if target == self.serial {
+                    okay(&format!("already connected to {}", target))
+                } else {
+                    okay(&format!("failed to connect to '{}': bridged to {}", target, self.serial))
+                }
+            } else if request.starts_with(&format!("host-serial:{}:", self.serial)) {
+                // Forwards would need listeners on the host side of the bridge, which it does not have.
+                Response::Fail(format!("unsupported on a direct connection: {}", request).into_bytes())
+            } else if request.starts_with("host-serial:") {
+                Response::Fail(b"device not found".to_vec())
+            } else {
+                Response::Fail(format!("unsupported on a direct connection: {}", request).into_bytes())
+            };
+            client.write_all(&codec::encode_response(&response)?)?;
+            if matches!(response, Response::Fail(_)) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Loopback smart-socket server in front of one authenticated adbd connection. Dropping it closes both.
+pub(crate) struct DirectBridge {
+    addr: SocketAddr,
+    device: Arc<Device>,
+    closed: Arc<AtomicBool>,
+}
+
+impl std::fmt::Debug for DirectBridge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DirectBridge")
+            .field("addr", &self.addr)
+            .field("serial", &self.device.serial)
+            .finish()
+    }
+}
+
+impl DirectBridge {
+    /// Connects and authenticates to the adbd at `target`, then starts serving on an ephemeral loopback port.
+    pub(crate) fn start(target: &str, key: &AdbKey, timeout: Duration) -> AGResult<Self> {
+        let mut stream = TcpStream::connect(target)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        let connected = handshake(&mut stream, key, target).map_err(|e| e.in_service(target, "CNXN"))?;
+        stream.set_read_timeout(None)?;
+        let device = Arc::new(Device {
+            serial: target.to_string(),
+            writer: Mutex::new(stream.try_clone()?),
+            routes: Mutex::new(HashMap::new()),
+            next_id: AtomicU32::new(1),
+            properties: parse_banner(&connected.payload),
+            max_payload: connected.arg1.clamp(1, MAX_PAYLOAD) as usize,
+            checksum: connected.arg0 < A_VERSION_SKIP_CHECKSUM,
+            timeout,
+        });
+        let reader = device.clone();
+        thread::spawn(move || reader.read_loop(stream));
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let closed = Arc::new(AtomicBool::new(false));
+        let (server, stop) = (device.clone(), closed.clone());
+        thread::spawn(move || {
+            for client in listener.incoming() {
+                if stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                let Ok(client) = client else { continue };
+                let server = server.clone();
+                thread::spawn(move || server.serve(client));
+            }
+        });
+        Ok(DirectBridge { addr, device, closed })
+    }
+
+    pub(crate) fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for DirectBridge {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+        let _ = self.device.writer.lock().unwrap().shutdown(Shutdown::Both);
+        // Wake the accept loop so it sees the flag.
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::MockAdbd;
+    use super::*;
+    use crate::controller::adb::{ForwardSpec, ShellOutput};
+    use crate::controller::{AdbBuilder, Controller};
+    use std::io::BufRead;
+
+    fn key_file(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("autogui-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        auth::write_private(&path, auth::TEST_KEY.as_bytes()).unwrap();
+        path
+    }
+
+    #[test]
+    fn banner_properties() {
+        let properties = parse_banner(b"device::ro.product.name=sdk;ro.product.model=Pixel 7;features=shell_v2,cmd\0");
+        assert_eq!(properties["ro.product.model"], "Pixel 7");
+        assert_eq!(properties["features"], "shell_v2,cmd");
+    }
+
+    #[test]
+    fn new_key_is_offered_then_trusted() {
+        let adbd = MockAdbd::start().with_reply("echo hi", "hi\n");
+        let key_path = key_file("offer");
+        let mut adb = AdbBuilder::new()
+            .with_connection(Connection::Direct)
+            .with_key_path(&key_path)
+            .with_target(adbd.addr())
+            .build()
+            .unwrap();
+        assert_eq!(adbd.auth_steps(), ["signature rejected", "public key accepted"]);
+        assert_eq!(
+            adb.shell_output("echo hi").unwrap(),
+            ShellOutput {
+                stdout: b"hi\n".to_vec(),
+                stderr: Vec::new(),
+                exit_code: 0
+            }
+        );
+        assert!(adbd.services().contains(&"shell,v2,raw:echo hi".to_string()));
+        let devices = adb.devices().unwrap();
+        assert_eq!(
+            (devices[0].serial.as_str(), devices[0].model.as_deref()),
+            (adbd.addr(), Some("Mock_adbd"))
+        );
+        drop(adb);
+
+        let _adb = AdbBuilder::new()
+            .with_connection(Connection::Direct)
+            .with_key_path(&key_path)
+            .with_target(adbd.addr())
+            .build()
+            .unwrap();
+        assert_eq!(adbd.auth_steps().last().map(|s| s.as_str()), Some("signature accepted"));
+        std::fs::remove_file(key_path).unwrap();
+    }
+
+    #[test]
+    fn silent_target_times_out() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let key_path = key_file("silent");
+        let result = AdbBuilder::new()
+            .with_connection(Connection::Direct)
+            .with_key_path(&key_path)
+            .with_target(&listener.local_addr().unwrap().to_string())
+            .with_timeout(Duration::from_millis(200))
+            .build();
+        assert!(matches!(result, Err(AGError::Timeout { .. })), "{:?}", result);
+        std::fs::remove_file(key_path).unwrap();
+    }
+
+    #[test]
+    fn forwards_are_unsupported() {
+        let adbd = MockAdbd::start();
+        let key_path = key_file("forward");
+        let mut adb = AdbBuilder::new()
+            .with_connection(Connection::Direct)
+            .with_key_path(&key_path)
+            .with_target(adbd.addr())
+            .build()
+            .unwrap();
+        let result = adb.forward(&ForwardSpec::Tcp(0), &ForwardSpec::LocalAbstract("minitouch".to_string()));
+        assert!(matches!(result, Err(AGError::AdbFail { message, .. }) if message.contains("unsupported on a direct connection")));
+        assert!(matches!(adb.enable_minitouch("minitouch"), Err(AGError::AdbFail { .. })));
+        std::fs::remove_file(key_path).unwrap();
+    }
+
+    #[test]
+    fn streams_are_multiplexed() {
+        let adbd = MockAdbd::start()
+            .with_endless_reply("logcat", "I/tag: line\n")
+            .with_reply("input tap 1 2", "");
+        let key_path = key_file("mux");
+        let mut adb = AdbBuilder::new()
+            .with_connection(Connection::Direct)
+            .with_key_path(&key_path)
+            .with_target(adbd.addr())
+            .build()
+            .unwrap();
+        let mut logcat = adb.shell_stream("logcat").unwrap();
+        adb.click(1, 2).unwrap();
+        let mut line = String::new();
+        logcat.read_line(&mut line).unwrap();
+        assert_eq!(line, "I/tag: line\n");
+        drop(logcat);
+        adb.click(1, 2).unwrap();
+        assert!(matches!(adb.shell("missing"), Ok(recv) if !recv.is_ok));
+        std::fs::remove_file(key_path).unwrap();
+    }
+}
@@ -0,0 +1,216 @@
+//! Finds local emulators by probing the ports their vendors use for adbd.
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use crate::error::AGResult;
+
+use super::{AdbBuilder, Connection, Emulator};
+
+/// `count` ports starting at `first`, `step` apart, that `vendor` is known to listen on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortRange {
+    pub vendor: Option<Emulator>,
+    pub first: u16,
+    pub count: u16,
+    pub step: u16,
+}
+
+impl PortRange {
+    pub fn new(vendor: Option<Emulator>, first: u16, count: u16, step: u16) -> Self {
+        PortRange { vendor, first, count, step }
+    }
+
+    pub fn ports(&self) -> impl Iterator<Item = u16> {
+        let (first, step) = (self.first as u32, self.step.max(1) as u32);
+        (0..self.count as u32).map_while(move |i| u16::try_from(first + i * step).ok())
+    }
+
+    /// Where the common emulators put their first instances and the ones after it.
+    pub fn defaults() -> Vec<PortRange> {
+        vec![
+            PortRange::new(Some(Emulator::MuMu), 7555, 1, 1),
+            PortRange::new(Some(Emulator::MuMu), 16384, 16, 32),
+            PortRange::new(Some(Emulator::LDPlayer), 5555, 16, 2),
+            PortRange::new(Some(Emulator::BlueStacks), 5555, 16, 10),
+            PortRange::new(Some(Emulator::Nox), 62001, 1, 1),
+            PortRange::new(Some(Emulator::Nox), 62025, 16, 1),
+        ]
+    }
+}
+
+/// Every port of `ranges` once, in order, with its vendor. A port claimed by several vendors, such as 5555, gets
+/// none, leaving it to `Emulator::detect`.
+fn port_table(ranges: &[PortRange]) -> Vec<(u16, Option<Emulator>)> {
+    let mut ports: Vec<(u16, Option<Emulator>)> = Vec::new();
+    for range in ranges {
+        for port in range.ports() {
+            match ports.iter_mut().find(|(p, _)| *p == port) {
+                Some((_, vendor)) if *vendor != range.vendor => *vendor = None,
+                Some(_) => {}
+                None => ports.push((port, range.vendor)),
+            }
+        }
+    }
+    ports
+}
+
+/// A device that answered on a probed port.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    /// What to pass to `AdbBuilder::with_target`.
+    pub target: String,
+    /// From `getprop` when it gives the vendor away, otherwise from the port table.
+    pub vendor: Option<Emulator>,
+    pub model: String,
+}
+
+/// Probes a port table on one host and confirms each open port with `host:connect` and `getprop`. Devices found stay
+/// connected to the ADB server.
+#[derive(Debug, Clone)]
+pub struct Discovery {
+    host: String,
+    ranges: Vec<PortRange>,
+    builder: AdbBuilder,
+    probe_timeout: Duration,
+}
+
+impl Default for Discovery {
+    fn default() -> Self {
+        Discovery {
+            host: "127.0.0.1".to_string(),
+            ranges: PortRange::defaults(),
+            builder: AdbBuilder::new(),
+            probe_timeout: Duration::from_millis(200),
+        }
+    }
+}
+
+impl Discovery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_host(mut self, host: &str) -> Self {
+        self.host = host.to_string();
+        self
+    }
+
+    /// Replaces the port table.
+    pub fn with_ranges(mut self, ranges: Vec<PortRange>) -> Self {
+        self.ranges = ranges;
+        self
+    }
+
+    pub fn with_range(mut self, range: PortRange) -> Self {
+        self.ranges.push(range);
+        self
+    }
+
+    /// How the ADB server is reached; its target, selector and connection kind are ignored.
+    pub fn with_builder(mut self, builder: AdbBuilder) -> Self {
+        self.builder = builder;
+        self
+    }
+
+    /// How long a port may take to accept a TCP connection before it is skipped.
+    pub fn with_probe_timeout(mut self, timeout: Duration) -> Self {
+        self.probe_timeout = timeout;
+        self
+    }
+
+    /// Candidates in port table order, each port probed once.
+    pub fn run(self) -> AGResult<Vec<Candidate>> {
+        let ports = port_table(&self.ranges);
+        let mut builder = self.builder;
+        builder.target = None;
+        builder.selector = None;
+        builder.connection = Connection::Server;
+        let mut adb = builder.build()?;
+        let mut candidates = Vec::new();
+        for (port, vendor) in ports {
+            let target = format!("{}:{}", self.host, port);
+            let open = match target.parse::<SocketAddr>() {
+                Ok(addr) => TcpStream::connect_timeout(&addr, self.probe_timeout).is_ok(),
+                Err(_) => TcpStream::connect(&target).is_ok(),
+            };
+            if !open || adb.connect(&target).is_err() {
+                continue;
+            }
+            let Ok(props) = adb.getprop() else {
+                continue;
+            };
+            candidates.push(Candidate {
+                vendor: Emulator::detect(&props).or(vendor),
+                model: props.get("ro.product.model").cloned().unwrap_or_default(),
+                target,
+            });
+        }
+        Ok(candidates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock::MockAdbServer;
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn port_ranges() {
+        assert_eq!(PortRange::new(None, 5555, 3, 10).ports().collect::<Vec<_>>(), [5555, 5565, 5575]);
+        assert_eq!(PortRange::new(None, 65534, 4, 1).ports().collect::<Vec<_>>(), [65534, 65535]);
+        assert!(PortRange::defaults().iter().any(|r| r.ports().any(|p| p == 7555)));
+
+        let table = port_table(&PortRange::defaults());
+        let vendor = |port: u16| table.iter().find(|(p, _)| *p == port).map(|(_, v)| *v);
+        assert_eq!(vendor(5555), Some(None));
+        assert_eq!(vendor(5565), Some(None));
+        assert_eq!(vendor(5557), Some(Some(Emulator::LDPlayer)));
+        assert_eq!(vendor(5595), Some(Some(Emulator::BlueStacks)));
+        assert_eq!(vendor(7555), Some(Some(Emulator::MuMu)));
+        assert_eq!(table.iter().filter(|(p, _)| *p == 5575).count(), 1);
+    }
+
+    #[test]
+    fn open_ports_become_candidates() {
+        let server = MockAdbServer::start().with_reply("getprop", "[ro.product.model]: [SM-S9080]\n");
+        let emulators = [TcpListener::bind("127.0.0.1:0").unwrap(), TcpListener::bind("127.0.0.1:0").unwrap()];
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let port = |i: usize| emulators[i].local_addr().unwrap().port();
+        let candidates = Discovery::new()
+            .with_ranges(vec![
+                PortRange::new(Some(Emulator::Nox), port(0), 1, 1),
+                PortRange::new(None, closed, 1, 1),
+                PortRange::new(None, port(1), 1, 1),
+                PortRange::new(Some(Emulator::Nox), port(0), 1, 1),
+            ])
+            .with_builder(AdbBuilder::new().with_addr(server.addr()).with_target("ignored:5555"))
+            .run()
+            .unwrap();
+        assert_eq!(
+            candidates,
+            [
+                Candidate {
+                    target: format!("127.0.0.1:{}", port(0)),
+                    vendor: Some(Emulator::Nox),
+                    model: "SM-S9080".to_string(),
+                },
+                Candidate {
+                    target: format!("127.0.0.1:{}", port(1)),
+                    vendor: None,
+                    model: "SM-S9080".to_string(),
+                },
+            ]
+        );
+        let requests = server.requests();
+        assert!(!requests.iter().any(|r| r.contains("ignored") || r.contains(&closed.to_string())));
+
+        server.set_reply("getprop", "[ro.product.model]: [SM-S9080]\n[init.svc.ldinit]: [running]\n");
+        let candidates = Discovery::new()
+            .with_ranges(vec![PortRange::new(Some(Emulator::Nox), port(0), 1, 1)])
+            .with_builder(AdbBuilder::new().with_addr(server.addr()))
+            .run()
+            .unwrap();
+        assert_eq!(candidates[0].vendor, Some(Emulator::LDPlayer));
+    }
+}
@@ -0,0 +1,152 @@
+//! Screen size and rotation from `wm size` and `dumpsys input`/`dumpsys display`.
+use crate::error::{AGError, AGResult};
+
+use super::ADB;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayInfo {
+    /// Panel size in the natural (rotation 0) orientation.
+    pub physical: (u32, u32),
+    /// Size set with `wm size WxH`, also in the natural orientation.
+    pub override_size: Option<(u32, u32)>,
+    /// Quarter turns clockwise, 0..=3.
+    pub rotation: u32,
+}
+
+impl DisplayInfo {
+    /// Width and height as `screenshot()` returns them: the override size if any, swapped when rotated by 90 or 270 degrees.
+    pub fn effective_size(&self) -> (u32, u32) {
+        let (w, h) = self.override_size.unwrap_or(self.physical);
+        if self.rotation % 2 == 1 {
+            (h, w)
+        } else {
+            (w, h)
+        }
+    }
+}
+
+fn parse_size(s: &str) -> Option<(u32, u32)> {
+    let (w, h) = s.trim().split_once('x')?;
+    Some((w.trim().parse().ok()?, h.trim().parse().ok()?))
+}
+
+/// Parses `wm size` output; the rotation is left at 0.
+pub(crate) fn parse_wm_size(output: &str) -> Option<DisplayInfo> {
+    let mut physical = None;
+    let mut override_size = None;
+    for line in output.lines() {
+        if let Some(size) = line.trim().strip_prefix("Physical size:") {
+            physical = parse_size(size);
+        } else if let Some(size) = line.trim().strip_prefix("Override size:") {
+            override_size = parse_size(size);
+        }
+    }
+    Some(DisplayInfo {
+        physical: physical?,
+        override_size,
+        rotation: 0,
+    })
+}
+
+/// First digit following `key` in `output`.
+fn find_digit_after(output: &str, key: &str) -> Option<u32> {
+    output
+        .match_indices(key)
+        .find_map(|(i, _)| output[i + key.len()..].trim_start().chars().next()?.to_digit(10))
+}
+
+/// Rotation of the default display from `dumpsys input` (`SurfaceOrientation: N`, or `orientation=N` in the viewport
+/// line on newer releases) or `dumpsys display` (`rotation N` in the display info).
+pub(crate) fn parse_rotation(output: &str) -> Option<u32> {
+    if let Some(rotation) = find_digit_after(output, "SurfaceOrientation:") {
+        return Some(rotation);
+    }
+    if let Some(line) = output
+        .lines()
+        .find(|l| l.contains("Viewport INTERNAL") || l.contains("viewport INTERNAL"))
+    {
+        if let Some(rotation) = find_digit_after(line, "orientation=") {
+            return Some(rotation);
+        }
+    }
+    find_digit_after(output, ", rotation ")
+}
+
+/// Greps the rotation lines out on the device, so one round trip returns a line or two instead of the full dumps.
+pub(crate) const ROTATION_COMMAND: &str =
+    "dumpsys input | grep -m 1 -i -E 'SurfaceOrientation|viewport internal' || dumpsys display | grep -m 1 ', rotation '";
+
+impl ADB {
+    pub(crate) fn rotation(&mut self) -> AGResult<u32> {
+        // Both greps failing leaves a non-zero exit, which the parse below reports better.
+        let output = self.shell_output(ROTATION_COMMAND)?.stdout_str();
+        parse_rotation(&output)
+            .map(|r| r % 4)
+            .ok_or_else(|| AGError::Custom("cannot find display rotation".to_string()))
+    }
+
+    /// Queries the display, reusing the cached `wm size` result until the rotation changes.
+    pub fn display_info(&mut self) -> AGResult<DisplayInfo> {
+        let rotation = self.rotation()?;
+        if let Some(info) = self.display.filter(|info| info.rotation == rotation) {
+            return Ok(info);
+        }
+        let output = self.shell_checked("wm size")?.stdout_str();
+        let info = parse_wm_size(&output).ok_or_else(|| AGError::Custom(format!("cannot parse `wm size`: {}", output.trim())))?;
+        let info = DisplayInfo { rotation, ..info };
+        self.display = Some(info);
+        Ok(info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock::MockAdbServer;
+    use super::*;
+    use crate::controller::{AdbBuilder, Controller};
+
+    #[test]
+    fn parse_outputs() {
+        let info = parse_wm_size("Physical size: 1080x1920\n").unwrap();
+        assert_eq!((info.physical, info.override_size), ((1080, 1920), None));
+        let info = parse_wm_size("Physical size: 1080x1920\nOverride size: 720x1280\n").unwrap();
+        assert_eq!((info.physical, info.override_size), ((1080, 1920), Some((720, 1280))));
+        assert_eq!(parse_wm_size("error"), None);
+        assert_eq!(parse_rotation("    SurfaceOrientation: 3\n"), Some(3));
+        assert_eq!(
+            parse_rotation("  Viewport INTERNAL: displayId=0, uniqueId=local:0, port=0, orientation=1, logicalFrame=[0, 0, 1920, 1080]"),
+            Some(1)
+        );
+        assert_eq!(
+            parse_rotation("mOverrideDisplayInfo=DisplayInfo{\"Built-in Screen\", real 1080 x 1920, rotation 2, density 480}"),
+            Some(2)
+        );
+        assert_eq!(parse_rotation("nothing here"), None);
+    }
+
+    #[test]
+    fn resolution_follows_rotation() {
+        let server = MockAdbServer::start()
+            .with_reply("wm size", "Physical size: 1080x1920\nOverride size: 720x1280\n")
+            .with_reply(ROTATION_COMMAND, "    SurfaceOrientation: 0\n");
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).with_target("emulator-5554").build().unwrap();
+        assert_eq!(adb.get_resolution().unwrap(), (720, 1280));
+        assert_eq!(adb.get_resolution().unwrap(), (720, 1280));
+        server.set_reply(ROTATION_COMMAND, "    SurfaceOrientation: 1\n");
+        assert_eq!(adb.get_resolution().unwrap(), (1280, 720));
+        let requests = server.requests();
+        assert_eq!(requests.iter().filter(|r| r.contains("wm size")).count(), 2);
+        assert_eq!(requests.iter().filter(|r| r.contains("dumpsys")).count(), 3);
+        assert!(requests.iter().all(|r| !r.ends_with("dumpsys input") && !r.ends_with("dumpsys display")));
+    }
+
+    #[test]
+    fn rotation_from_dumpsys_display_line() {
+        let server = MockAdbServer::start().with_reply("wm size", "Physical size: 1080x1920\n").with_reply(
+            ROTATION_COMMAND,
+            "DisplayInfo{\"Built-in Screen\", real 1920 x 1080, rotation 3, density 480}\n",
+        );
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).with_target("emulator-5554").build().unwrap();
+        assert_eq!(adb.get_resolution().unwrap(), (1920, 1080));
+    }
+}
@@ -0,0 +1,229 @@
+//! Port forwarding through the ADB server (`host-serial:<serial>:forward:`) and reverse forwarding through adbd
+//! (`reverse:forward:`), e.g. to reach helper agents listening on the device.
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{AGError, AGResult};
+
+use super::codec::{self, Status};
+use super::ADB;
+
+/// One end of a forward.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ForwardSpec {
+    /// `tcp:<port>`; port 0 on the listening side lets the server pick a free one.
+    Tcp(u16),
+    /// `localabstract:<name>`, an abstract-namespace Unix socket such as the ones minitouch and scrcpy use.
+    LocalAbstract(String),
+    /// `localreserved:<name>`, a socket in `/dev/socket`.
+    LocalReserved(String),
+    /// `localfilesystem:<path>`.
+    LocalFilesystem(String),
+}
+
+impl fmt::Display for ForwardSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ForwardSpec::Tcp(port) => write!(f, "tcp:{}", port),
+            ForwardSpec::LocalAbstract(name) => write!(f, "localabstract:{}", name),
+            ForwardSpec::LocalReserved(name) => write!(f, "localreserved:{}", name),
+            ForwardSpec::LocalFilesystem(path) => write!(f, "localfilesystem:{}", path),
+        }
+    }
+}
+
+impl FromStr for ForwardSpec {
+    type Err = AGError;
+
+    fn from_str(s: &str) -> AGResult<Self> {
+        let (kind, value) = s.split_once(':').ok_or(AGError::Decode)?;
+        match kind {
+            "tcp" => value.parse().map(ForwardSpec::Tcp).map_err(|_| AGError::Decode),
+            "localabstract" => Ok(ForwardSpec::LocalAbstract(value.to_string())),
+            "localreserved" => Ok(ForwardSpec::LocalReserved(value.to_string())),
+            "localfilesystem" => Ok(ForwardSpec::LocalFilesystem(value.to_string())),
+            _ => Err(AGError::Decode),
+        }
+    }
+}
+
+/// An active forward. For reverse forwards `local` is still the host side and `remote` the device side.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Forward {
+    pub serial: String,
+    pub local: ForwardSpec,
+    pub remote: ForwardSpec,
+}
+
+/// Parses `list-forward` output, one `<serial> <listening> <connecting>` line per forward. Lines with spec kinds this
+/// crate does not model, such as `jdwp:`, are skipped.
+fn parse_list(output: &str, reverse: bool) -> Vec<Forward> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let serial = fields.next()?.to_string();
+            let listening = fields.next()?.parse().ok()?;
+            let connecting = fields.next()?.parse().ok()?;
+            let (local, remote) = if reverse { (connecting, listening) } else { (listening, connecting) };
+            Some(Forward { serial, local, remote })
+        })
+        .collect()
+}
+
+impl ADB {
+    /// Reads the second status both forward services send once the first `OKAY` accepted the request, and the
+    /// allocated port that follows it for `tcp:0`.
+    fn forward_result(&mut self, service: &str, listening: &ForwardSpec) -> AGResult<ForwardSpec> {
+        let result = (|| {
+            if codec::read_status(&mut self.stream)? == Status::Fail {
+                let message = String::from_utf8_lossy(&codec::read_message(&mut self.stream)?).to_string();
+                return Err(AGError::adb_fail(&self.target, service, message));
+            }
+            if *listening != ForwardSpec::Tcp(0) {
+                return Ok(listening.clone());
+            }
+            let port = String::from_utf8_lossy(&codec::read_message(&mut self.stream)?).to_string();
+            port.trim().parse().map(ForwardSpec::Tcp).map_err(|_| AGError::Decode)
+        })();
+        self.reset()?;
+        result.map_err(|e| e.in_service(&self.target, service))
+    }
+
+    /// Forwards connections to `local` on the host to `remote` on the device and returns the local spec, with the
+    /// actual port if `tcp:0` was asked for. The forward is removed again when the `ADB` is dropped.
+    pub fn forward(&mut self, local: &ForwardSpec, remote: &ForwardSpec) -> AGResult<ForwardSpec> {
+        let service = format!("host-serial:{}:forward:{};{}", self.target, local, remote);
+        self.open_service(&service)?;
+        let local = self.forward_result(&service, local)?;
+        // Rebinding a listening side replaces the old forward in adb, so it stays a single entry here.
+        if !self.forwards.contains(&local) {
+            self.forwards.push(local.clone());
+        }
+        Ok(local)
+    }
+
+    /// Forwards of the current device, including ones set up by other clients.
+    pub fn list_forwards(&mut self) -> AGResult<Vec<Forward>> {
+        let output = self.host_request(&format!("host-serial:{}:list-forward", self.target))?;
+        Ok(parse_list(&output, false).into_iter().filter(|f| f.serial == self.target).collect())
+    }
+
+    pub fn remove_forward(&mut self, local: &ForwardSpec) -> AGResult<()> {
+        let service = format!("host-serial:{}:killforward:{}", self.target, local);
+        self.open_service(&service)?;
+        self.forward_result(&service, local)?;
+        self.forwards.retain(|f| f != local);
+        Ok(())
+    }
+
+    /// Forwards connections to `remote` on the device to `local` on the host and returns the device-side spec.
+    /// Removed again when the `ADB` is dropped.
+    pub fn reverse(&mut self, remote: &ForwardSpec, local: &ForwardSpec) -> AGResult<ForwardSpec> {
+        let service = format!("reverse:forward:{};{}", remote, local);
+        self.transport()?;
+        self.open_service(&service)?;
+        let remote = self.forward_result(&service, remote)?;
+        if !self.reverses.contains(&remote) {
+            self.reverses.push(remote.clone());
+        }
+        Ok(remote)
+    }
+
+    pub fn list_reverses(&mut self) -> AGResult<Vec<Forward>> {
+        self.transport()?;
+        let output = self.host_request("reverse:list-forward")?;
+        let serial = self.target.clone();
+        Ok(parse_list(&output, true)
+            .into_iter()
+            .map(|f| Forward { serial: serial.clone(), ..f })
+            .collect())
+    }
+
+    pub fn remove_reverse(&mut self, remote: &ForwardSpec) -> AGResult<()> {
+        let service = format!("reverse:killforward:{}", remote);
+        self.transport()?;
+        self.open_service(&service)?;
+        self.forward_result(&service, remote)?;
+        self.reverses.retain(|f| f != remote);
+        Ok(())
+    }
+
+    /// Removes the forwards and reverse forwards this `ADB` created. Errors are ignored since the device may be gone.
+    pub(crate) fn remove_forwards(&mut self) {
+        for local in std::mem::take(&mut self.forwards) {
+            let _ = self.remove_forward(&local);
+        }
+        for remote in std::mem::take(&mut self.reverses) {
+            let _ = self.remove_reverse(&remote);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock::MockAdbServer;
+    use super::*;
+    use crate::controller::AdbBuilder;
+
+    #[test]
+    fn specs_roundtrip() {
+        for spec in [
+            "tcp:1717",
+            "localabstract:minitouch",
+            "localreserved:x",
+            "localfilesystem:/data/local/tmp/s",
+        ] {
+            assert_eq!(spec.parse::<ForwardSpec>().unwrap().to_string(), spec);
+        }
+        assert!("tcp:x".parse::<ForwardSpec>().is_err());
+        assert!("jdwp:123".parse::<ForwardSpec>().is_err());
+        let list = parse_list("emulator-5554 tcp:0 jdwp:1\nhost-1 localabstract:scrcpy tcp:27183\n", true);
+        assert_eq!(
+            list,
+            [Forward {
+                serial: "host-1".to_string(),
+                local: ForwardSpec::Tcp(27183),
+                remote: ForwardSpec::LocalAbstract("scrcpy".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn forwards_are_listed_and_removed_on_drop() {
+        let server = MockAdbServer::start().with_device("other-1");
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).with_target("emulator-5554").build().unwrap();
+        let minitouch = ForwardSpec::LocalAbstract("minitouch".to_string());
+        let local = adb.forward(&ForwardSpec::Tcp(0), &minitouch).unwrap();
+        assert!(matches!(local, ForwardSpec::Tcp(port) if port != 0));
+        adb.forward(&ForwardSpec::Tcp(1313), &ForwardSpec::Tcp(1313)).unwrap();
+        let forwards = adb.list_forwards().unwrap();
+        assert_eq!(forwards.len(), 2);
+        assert_eq!((&forwards[0].local, &forwards[0].remote), (&local, &minitouch));
+
+        adb.remove_forward(&ForwardSpec::Tcp(1313)).unwrap();
+        assert!(matches!(
+            adb.remove_forward(&ForwardSpec::Tcp(1313)),
+            Err(AGError::AdbFail { message, .. }) if message.contains("not found")
+        ));
+
+        let remote = adb.reverse(&ForwardSpec::Tcp(8080), &ForwardSpec::Tcp(9090)).unwrap();
+        assert_eq!(remote, ForwardSpec::Tcp(8080));
+        let reverses = adb.list_reverses().unwrap();
+        assert_eq!((&reverses[0].local, &reverses[0].remote), (&ForwardSpec::Tcp(9090), &remote));
+        adb.reverse(&ForwardSpec::Tcp(8080), &ForwardSpec::Tcp(9091)).unwrap();
+        adb.forward(&ForwardSpec::Tcp(1414), &ForwardSpec::Tcp(1414)).unwrap();
+        adb.forward(&ForwardSpec::Tcp(1414), &ForwardSpec::Tcp(1415)).unwrap();
+        assert_eq!((adb.forwards.len(), adb.reverses.len()), (2, 1));
+
+        drop(adb);
+        let requests = server.requests();
+        assert!(requests.contains(&format!("host-serial:emulator-5554:killforward:{}", local)));
+        let count = |request: &str| requests.iter().filter(|r| *r == request).count();
+        assert_eq!(count("reverse:killforward:tcp:8080"), 1);
+        assert_eq!(count("host-serial:emulator-5554:killforward:tcp:1414"), 1);
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).with_target("emulator-5554").build().unwrap();
+        assert!(adb.list_forwards().unwrap().is_empty());
+        assert!(adb.list_reverses().unwrap().is_empty());
+    }
+}
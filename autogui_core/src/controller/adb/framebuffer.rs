@@ -0,0 +1,208 @@
+//! Screenshots through the `framebuffer:` service, which sends a header and raw pixels without spawning a shell.
+use std::io::{Read, Write};
+
+use image::RgbaImage;
+
+use crate::error::{AGError, AGResult};
+
+use super::{PixelFormat, ScreenshotMode, ADB};
+
+/// Bit position and width of one colour channel within a pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Channel {
+    pub offset: u32,
+    pub length: u32,
+}
+
+/// The `fbinfo` header: version 1 has no colour space, version 2 adds it after `bpp`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FramebufferHeader {
+    pub version: u32,
+    pub bpp: u32,
+    pub color_space: Option<u32>,
+    pub size: u32,
+    pub width: u32,
+    pub height: u32,
+    pub red: Channel,
+    pub green: Channel,
+    pub blue: Channel,
+    pub alpha: Channel,
+}
+
+impl FramebufferHeader {
+    /// Reads the header, returning `None` for versions other than 1 and 2.
+    pub fn read(stream: &mut impl Read) -> AGResult<Option<Self>> {
+        let mut next = || -> AGResult<u32> {
+            let mut buf = [0u8; 4];
+            stream.read_exact(&mut buf)?;
+            Ok(u32::from_le_bytes(buf))
+        };
+        let version = next()?;
+        if version != 1 && version != 2 {
+            return Ok(None);
+        }
+        let bpp = next()?;
+        let color_space = if version == 2 { Some(next()?) } else { None };
+        let (size, width, height) = (next()?, next()?, next()?);
+        let mut channel = || -> AGResult<Channel> {
+            Ok(Channel {
+                offset: next()?,
+                length: next()?,
+            })
+        };
+        let red = channel()?;
+        let blue = channel()?;
+        let green = channel()?;
+        let alpha = channel()?;
+        Ok(Some(FramebufferHeader {
+            version,
+            bpp,
+            color_space,
+            size,
+            width,
+            height,
+            red,
+            green,
+            blue,
+            alpha,
+        }))
+    }
+
+    /// The matching `screencap` pixel format, for the RGB565, RGBA8888, RGBX8888 and BGRA8888 layouts.
+    pub fn pixel_format(&self) -> Option<PixelFormat> {
+        let layout = (self.bpp, self.red.offset, self.green.offset, self.blue.offset, self.alpha.length);
+        match layout {
+            (16, 11, 5, 0, 0) => Some(PixelFormat::Rgb565),
+            (32, 0, 8, 16, 8) => Some(PixelFormat::Rgba8888),
+            (32, 0, 8, 16, 0) => Some(PixelFormat::Rgbx8888),
+            (32, 16, 8, 0, 8) => Some(PixelFormat::Bgra8888),
+            (24, 0, 8, 16, 0) => Some(PixelFormat::Rgb888),
+            _ => None,
+        }
+    }
+
+    pub fn to_rgba(&self, pixels: &[u8]) -> AGResult<RgbaImage> {
+        let format = self
+            .pixel_format()
+            .ok_or_else(|| AGError::Custom(format!("unsupported framebuffer layout {:?}", self)))?;
+        format.to_rgba(self.width, self.height, pixels)
+    }
+}
+
+impl ADB {
+    /// Captures through `framebuffer:`. Returns `None` when the device refuses the service, closes it before a full
+    /// header, or sends an unknown header.
+    pub fn framebuffer(&mut self) -> AGResult<Option<RgbaImage>> {
+        self.transport()?;
+        match self.open_service("framebuffer:") {
+            Ok(()) => {}
+            Err(AGError::AdbFail { .. }) => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let result = self.read_framebuffer();
+        self.reset()?;
+        result
+    }
+
+    fn read_framebuffer(&mut self) -> AGResult<Option<RgbaImage>> {
+        // adbd accepts the service even when nothing can capture behind it, and then just closes the stream.
+        let header = match FramebufferHeader::read(&mut self.stream) {
+            Err(AGError::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            header => header?,
+        };
+        let Some(header) = header else {
+            return Ok(None);
+        };
+        if header.pixel_format().is_none() || header.size == 0 {
+            return Ok(None);
+        }
+        // Pre-Lollipop adbd waits for one byte before sending the pixels.
+        self.stream.write_all(&[0])?;
+        let mut pixels = vec![0u8; header.size as usize];
+        self.stream.read_exact(&mut pixels)?;
+        header.to_rgba(&pixels).map(Some)
+    }
+
+    /// `framebuffer:` with a fallback to raw `screencap`, which becomes the configured mode once the service is refused.
+    pub(crate) fn framebuffer_or_screencap(&mut self) -> AGResult<RgbaImage> {
+        if let Some(img) = self.framebuffer()? {
+            return Ok(img);
+        }
+        if self.screenshot_mode == ScreenshotMode::Framebuffer {
+            self.screenshot_mode = ScreenshotMode::Raw;
+        }
+        self.screencap(ScreenshotMode::Raw)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock::MockAdbServer;
+    use super::*;
+    use crate::controller::{AdbBuilder, Controller};
+
+    fn sample() -> RgbaImage {
+        RgbaImage::from_fn(4, 2, |x, y| image::Rgba([x as u8 * 60, y as u8 * 100, 9, 255]))
+    }
+
+    #[test]
+    fn reads_v1_and_v2_headers() {
+        let v1: Vec<u8> = [1u32, 16, 4, 1, 2, 11, 5, 0, 5, 5, 6, 0, 0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let header = FramebufferHeader::read(&mut v1.as_slice()).unwrap().unwrap();
+        assert_eq!((header.width, header.height, header.color_space), (1, 2, None));
+        assert_eq!(header.pixel_format(), Some(PixelFormat::Rgb565));
+        let v2: Vec<u8> = [2u32, 32, 1, 16, 2, 2, 16, 8, 0, 8, 8, 8, 24, 8]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let header = FramebufferHeader::read(&mut v2.as_slice()).unwrap().unwrap();
+        assert_eq!((header.size, header.color_space), (16, Some(1)));
+        assert_eq!(header.pixel_format(), Some(PixelFormat::Bgra8888));
+        let legacy: Vec<u8> = [16u32, 0, 0, 0].iter().flat_map(|v| v.to_le_bytes()).collect();
+        assert_eq!(FramebufferHeader::read(&mut legacy.as_slice()).unwrap(), None);
+    }
+
+    #[test]
+    fn framebuffer_backend() {
+        let img = sample();
+        let server = MockAdbServer::start().with_framebuffer(&img, 2);
+        let mut adb = AdbBuilder::new()
+            .with_addr(server.addr())
+            .with_target("emulator-5554")
+            .with_screenshot_mode(ScreenshotMode::Framebuffer)
+            .build()
+            .unwrap();
+        assert_eq!(adb.screenshot().unwrap(), img);
+        assert!(!server.requests().iter().any(|r| r.contains("screencap")));
+    }
+
+    #[test]
+    fn falls_back_to_screencap() {
+        let img = sample();
+        let server = MockAdbServer::start().with_raw_screenshot(&img);
+        let mut adb = AdbBuilder::new()
+            .with_addr(server.addr())
+            .with_target("emulator-5554")
+            .with_screenshot_mode(ScreenshotMode::Framebuffer)
+            .build()
+            .unwrap();
+        assert_eq!(adb.screenshot().unwrap(), img);
+        assert_eq!(adb.screenshot_mode, ScreenshotMode::Raw);
+        assert_eq!(adb.screenshot().unwrap(), img);
+        assert_eq!(server.requests().iter().filter(|r| *r == "framebuffer:").count(), 1);
+    }
+
+    #[test]
+    fn closed_service_falls_back_to_screencap() {
+        let img = sample();
+        let server = MockAdbServer::start().with_raw_screenshot(&img).with_closed_framebuffer();
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).with_target("emulator-5554").build().unwrap();
+        assert_eq!(adb.framebuffer().unwrap(), None);
+        adb.screenshot_mode = ScreenshotMode::Framebuffer;
+        assert_eq!(adb.screenshot().unwrap(), img);
+        assert_eq!(adb.screenshot_mode, ScreenshotMode::Raw);
+    }
+}
@@ -0,0 +1,532 @@
+//! The accessibility tree from `uiautomator dump`, and selectors to find elements in it by attribute or by an
+//! XPath-like path such as `//android.widget.Button[@text='OK']`.
+use crate::controller::Rect;
+use crate::error::{AGError, AGResult};
+
+use super::ADB;
+
+/// Where the dump is written on the device before it is read back.
+const DUMP_PATH: &str = "/data/local/tmp/autogui_hierarchy.xml";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UiNode {
+    pub index: usize,
+    pub class: String,
+    pub package: String,
+    pub text: String,
+    pub resource_id: String,
+    pub content_desc: String,
+    pub bounds: Rect,
+    pub clickable: bool,
+    pub long_clickable: bool,
+    pub scrollable: bool,
+    pub enabled: bool,
+    pub checked: bool,
+    pub focused: bool,
+    pub selected: bool,
+    pub children: Vec<UiNode>,
+}
+
+impl UiNode {
+    /// An attribute by its name in the dump, e.g. `resource-id`, as a path predicate sees it.
+    pub fn attribute(&self, name: &str) -> Option<String> {
+        let flag = |b: bool| Some(b.to_string());
+        match name {
+            "index" => Some(self.index.to_string()),
+            "class" => Some(self.class.clone()),
+            "package" => Some(self.package.clone()),
+            "text" => Some(self.text.clone()),
+            "resource-id" => Some(self.resource_id.clone()),
+            "content-desc" => Some(self.content_desc.clone()),
+            "bounds" => {
+                let b = self.bounds;
+                Some(format!("[{},{}][{},{}]", b.left, b.top, b.right, b.bottom))
+            }
+            "clickable" => flag(self.clickable),
+            "long-clickable" => flag(self.long_clickable),
+            "scrollable" => flag(self.scrollable),
+            "enabled" => flag(self.enabled),
+            "checked" => flag(self.checked),
+            "focused" => flag(self.focused),
+            "selected" => flag(self.selected),
+            _ => None,
+        }
+    }
+
+    /// Whether the node test of a path step matches: `*` and `node` match anything, otherwise the class name, either
+    /// fully qualified or without its package.
+    fn is(&self, test: &str) -> bool {
+        test == "*" || test == "node" || self.class == test || self.class.rsplit('.').next() == Some(test)
+    }
+
+    fn descendants<'a>(&'a self, out: &mut Vec<&'a UiNode>) {
+        for child in &self.children {
+            out.push(child);
+            child.descendants(out);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Selector {
+    Text(String),
+    TextContains(String),
+    ResourceId(String),
+    Class(String),
+    ContentDesc(String),
+    /// An XPath subset: `/` and `//` steps, node tests by class, and predicates `[@attr='v']`,
+    /// `[contains(@attr,'v')]` and `[n]`, the n-th match among its siblings.
+    Path(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Predicate {
+    Equals(String, String),
+    Contains(String, String),
+    Position(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Step {
+    descendant: bool,
+    test: String,
+    predicates: Vec<Predicate>,
+}
+
+fn parse_predicate(pred: &str) -> AGResult<Predicate> {
+    let pred = pred.trim();
+    let quoted = |s: &str| -> AGResult<String> {
+        let s = s.trim();
+        let inner = s.strip_prefix('\'').and_then(|s| s.strip_suffix('\''));
+        let inner = inner.or_else(|| s.strip_prefix('"').and_then(|s| s.strip_suffix('"')));
+        inner.map(String::from).ok_or(AGError::Decode)
+    };
+    if let Ok(n) = pred.parse() {
+        return Ok(Predicate::Position(n));
+    }
+    if let Some(args) = pred.strip_prefix("contains(").and_then(|p| p.strip_suffix(')')) {
+        let (attr, value) = args.split_once(',').ok_or(AGError::Decode)?;
+        let attr = attr.trim().strip_prefix('@').ok_or(AGError::Decode)?;
+        return Ok(Predicate::Contains(attr.to_string(), quoted(value)?));
+    }
+    let (attr, value) = pred.strip_prefix('@').and_then(|p| p.split_once('=')).ok_or(AGError::Decode)?;
+    Ok(Predicate::Equals(attr.trim().to_string(), quoted(value)?))
+}
+
+fn parse_path(path: &str) -> AGResult<Vec<Step>> {
+    let invalid = || AGError::Custom(format!("invalid hierarchy path `{}`", path));
+    let mut steps = Vec::new();
+    let mut rest = path.trim();
+    while !rest.is_empty() {
+        let after = rest.strip_prefix('/').ok_or_else(invalid)?;
+        let (descendant, after) = match after.strip_prefix('/') {
+            Some(after) => (true, after),
+            None => (false, after),
+        };
+        let end = after.find(['[', '/']).unwrap_or(after.len());
+        let test = after[..end].trim();
+        if test.is_empty() {
+            return Err(invalid());
+        }
+        rest = &after[end..];
+        let mut predicates = Vec::new();
+        while let Some(inner) = rest.strip_prefix('[') {
+            let mut quote = None;
+            let close = inner
+                .char_indices()
+                .find(|&(_, c)| {
+                    match (quote, c) {
+                        (None, '\'' | '"') => quote = Some(c),
+                        (Some(q), c) if q == c => quote = None,
+                        _ => {}
+                    }
+                    quote.is_none() && c == ']'
+                })
+                .map(|(i, _)| i)
+                .ok_or_else(invalid)?;
+            predicates.push(parse_predicate(&inner[..close]).map_err(|_| invalid())?);
+            rest = &inner[close + 1..];
+        }
+        steps.push(Step {
+            descendant,
+            test: test.to_string(),
+            predicates,
+        });
+    }
+    if steps.is_empty() {
+        return Err(invalid());
+    }
+    Ok(steps)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UiHierarchy {
+    /// Quarter turns of the display when the dump was taken; bounds are in the rotated screen coordinates.
+    pub rotation: u32,
+    pub roots: Vec<UiNode>,
+}
+
+impl UiHierarchy {
+    pub fn parse(xml: &str) -> AGResult<Self> {
+        let mut hierarchy = UiHierarchy::default();
+        let mut stack: Vec<UiNode> = Vec::new();
+        for tag in xml::tags(xml)? {
+            match tag {
+                xml::Tag::Open {
+                    name: "hierarchy", attrs, ..
+                } => {
+                    hierarchy.rotation = xml::attr(&attrs, "rotation").and_then(|r| r.parse().ok()).unwrap_or(0);
+                }
+                xml::Tag::Open { name: "node", attrs, closed } => {
+                    let node = node_from(&attrs);
+                    if closed {
+                        push_node(&mut hierarchy, &mut stack, node);
+                    } else {
+                        stack.push(node);
+                    }
+                }
+                xml::Tag::Close("node") => {
+                    let node = stack.pop().ok_or(AGError::Decode)?;
+                    push_node(&mut hierarchy, &mut stack, node);
+                }
+                _ => {}
+            }
+        }
+        if !stack.is_empty() {
+            return Err(AGError::Decode);
+        }
+        Ok(hierarchy)
+    }
+
+    /// All nodes in document order.
+    pub fn nodes(&self) -> Vec<&UiNode> {
+        let mut nodes = Vec::new();
+        for root in &self.roots {
+            nodes.push(root);
+            root.descendants(&mut nodes);
+        }
+        nodes
+    }
+
+    /// Nodes matching `selector` in document order. Only `Selector::Path` can fail, on a malformed path.
+    pub fn find_all(&self, selector: &Selector) -> AGResult<Vec<&UiNode>> {
+        let matches = |f: &dyn Fn(&UiNode) -> bool| self.nodes().into_iter().filter(|n| f(n)).collect();
+        Ok(match selector {
+            Selector::Text(text) => matches(&|n| n.text == *text),
+            Selector::TextContains(text) => matches(&|n| n.text.contains(text.as_str())),
+            Selector::ResourceId(id) => matches(&|n| n.resource_id == *id || n.resource_id.ends_with(&format!(":id/{}", id))),
+            Selector::Class(class) => matches(&|n| n.is(class)),
+            Selector::ContentDesc(desc) => matches(&|n| n.content_desc == *desc),
+            Selector::Path(path) => self.select(path)?,
+        })
+    }
+
+    pub fn find(&self, selector: &Selector) -> AGResult<Option<&UiNode>> {
+        Ok(self.find_all(selector)?.into_iter().next())
+    }
+
+    fn select(&self, path: &str) -> AGResult<Vec<&UiNode>> {
+        let steps = parse_path(path)?;
+        // `None` is the document root above the `<node>` roots.
+        let mut context: Vec<Option<&UiNode>> = vec![None];
+        for step in &steps {
+            let mut next: Vec<&UiNode> = Vec::new();
+            for parent in &context {
+                // `//x` is `/descendant-or-self::node()/x`, so candidates are grouped by parent and `[n]` counts
+                // among siblings, as in XPath.
+                let mut parents: Vec<Option<&UiNode>> = vec![*parent];
+                if step.descendant {
+                    match parent {
+                        None => parents.extend(self.nodes().into_iter().map(Some)),
+                        Some(node) => {
+                            let mut descendants = Vec::new();
+                            node.descendants(&mut descendants);
+                            parents.extend(descendants.into_iter().map(Some));
+                        }
+                    }
+                }
+                for siblings in parents {
+                    let siblings = match siblings {
+                        None => &self.roots,
+                        Some(node) => &node.children,
+                    };
+                    for node in step.apply(siblings.iter().collect()) {
+                        if !next.iter().any(|n| std::ptr::eq(*n, node)) {
+                            next.push(node);
+                        }
+                    }
+                }
+            }
+            context = next.into_iter().map(Some).collect();
+        }
+        let selected: Vec<&UiNode> = context.into_iter().flatten().collect();
+        Ok(self
+            .nodes()
+            .into_iter()
+            .filter(|n| selected.iter().any(|s| std::ptr::eq(*s, *n)))
+            .collect())
+    }
+}
+
+impl Step {
+    /// Filters `siblings`, the children of one parent in document order, by the node test and predicates.
+    fn apply<'a>(&self, mut siblings: Vec<&'a UiNode>) -> Vec<&'a UiNode> {
+        siblings.retain(|n| n.is(&self.test));
+        for predicate in &self.predicates {
+            siblings = match predicate {
+                Predicate::Equals(attr, value) => siblings.into_iter().filter(|n| n.attribute(attr).as_ref() == Some(value)).collect(),
+                Predicate::Contains(attr, value) => siblings
+                    .into_iter()
+                    .filter(|n| n.attribute(attr).is_some_and(|a| a.contains(value.as_str())))
+                    .collect(),
+                Predicate::Position(n) => siblings.into_iter().skip(n.saturating_sub(1)).take(1).collect(),
+            };
+        }
+        siblings
+    }
+}
+
+fn push_node(hierarchy: &mut UiHierarchy, stack: &mut [UiNode], node: UiNode) {
+    match stack.last_mut() {
+        Some(parent) => parent.children.push(node),
+        None => hierarchy.roots.push(node),
+    }
+}
+
+/// Bounds as `[left,top][right,bottom]`.
+fn parse_bounds(bounds: &str) -> Option<Rect> {
+    let numbers: Vec<u32> = bounds
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse().ok())
+        .collect::<Option<_>>()?;
+    match numbers[..] {
+        [left, top, right, bottom] => Some(Rect::new(left, top, right, bottom)),
+        _ => None,
+    }
+}
+
+fn node_from(attrs: &[(&str, String)]) -> UiNode {
+    let text = |name: &str| xml::attr(attrs, name).unwrap_or_default().to_string();
+    let flag = |name: &str| xml::attr(attrs, name) == Some("true");
+    UiNode {
+        index: xml::attr(attrs, "index").and_then(|i| i.parse().ok()).unwrap_or(0),
+        class: text("class"),
+        package: text("package"),
+        text: text("text"),
+        resource_id: text("resource-id"),
+        content_desc: text("content-desc"),
+        bounds: xml::attr(attrs, "bounds").and_then(parse_bounds).unwrap_or_default(),
+        clickable: flag("clickable"),
+        long_clickable: flag("long-clickable"),
+        scrollable: flag("scrollable"),
+        enabled: flag("enabled"),
+        checked: flag("checked"),
+        focused: flag("focused"),
+        selected: flag("selected"),
+        children: Vec::new(),
+    }
+}
+
+/// Just enough XML for uiautomator dumps: elements with quoted attributes. Text content, the prolog, comments and
+/// doctypes are skipped.
+mod xml {
+    use crate::error::{AGError, AGResult};
+
+    #[derive(Debug, PartialEq, Eq)]
+    pub(super) enum Tag<'a> {
+        Open {
+            name: &'a str,
+            attrs: Vec<(&'a str, String)>,
+            closed: bool,
+        },
+        Close(&'a str),
+    }
+
+    pub(super) fn attr<'a>(attrs: &'a [(&str, String)], name: &str) -> Option<&'a str> {
+        attrs.iter().find(|(n, _)| *n == name).map(|(_, v)| v.as_str())
+    }
+
+    fn unescape(value: &str) -> String {
+        let mut out = String::with_capacity(value.len());
+        let mut rest = value;
+        while let Some(amp) = rest.find('&') {
+            out.push_str(&rest[..amp]);
+            rest = &rest[amp..];
+            let Some(semi) = rest.find(';') else { break };
+            let entity = &rest[1..semi];
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ => entity
+                    .strip_prefix("#x")
+                    .map(|hex| u32::from_str_radix(hex, 16).ok())
+                    .unwrap_or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                    .and_then(char::from_u32),
+            };
+            match c {
+                Some(c) => {
+                    out.push(c);
+                    rest = &rest[semi + 1..];
+                }
+                None => {
+                    out.push('&');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+
+    pub(super) fn tags(xml: &str) -> AGResult<Vec<Tag<'_>>> {
+        let mut tags = Vec::new();
+        let mut rest = xml;
+        while let Some(start) = rest.find('<') {
+            rest = &rest[start..];
+            for (open, close) in [("<?", "?>"), ("<!--", "-->"), ("<!", ">")] {
+                if rest.starts_with(open) {
+                    let end = rest.find(close).ok_or(AGError::Decode)?;
+                    rest = &rest[end + close.len()..];
+                }
+            }
+            if !rest.starts_with('<') {
+                continue;
+            }
+            if let Some(after) = rest.strip_prefix("</") {
+                let end = after.find('>').ok_or(AGError::Decode)?;
+                tags.push(Tag::Close(after[..end].trim()));
+                rest = &after[end + 1..];
+                continue;
+            }
+            let after = &rest[1..];
+            let name_end = after.find(|c: char| c.is_whitespace() || c == '/' || c == '>').ok_or(AGError::Decode)?;
+            let name = &after[..name_end];
+            let mut body = &after[name_end..];
+            let mut attrs = Vec::new();
+            let closed = loop {
+                body = body.trim_start();
+                if let Some(after) = body.strip_prefix("/>") {
+                    body = after;
+                    break true;
+                }
+                if let Some(after) = body.strip_prefix('>') {
+                    body = after;
+                    break false;
+                }
+                let eq = body.find('=').ok_or(AGError::Decode)?;
+                let attr_name = body[..eq].trim();
+                let value = body[eq + 1..].trim_start();
+                let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'').ok_or(AGError::Decode)?;
+                let end = value[1..].find(quote).ok_or(AGError::Decode)?;
+                attrs.push((attr_name, unescape(&value[1..end + 1])));
+                body = &value[end + 2..];
+            };
+            tags.push(Tag::Open { name, attrs, closed });
+            rest = body;
+        }
+        Ok(tags)
+    }
+}
+
+/// Keeps the status of the dump past the cleanup. `[ ]` rather than `exit` sets it, since the legacy shell path
+/// appends its exit marker after the command.
+fn dump_command() -> String {
+    format!("uiautomator dump {0} >/dev/null && cat {0}; s=$?; rm -f {0}; [ $s -eq 0 ]", DUMP_PATH)
+}
+
+impl ADB {
+    /// Dumps the current window's accessibility tree with `uiautomator dump`. Takes a second or more, and fails
+    /// while the UI keeps animating.
+    pub fn dump_hierarchy(&mut self) -> AGResult<UiHierarchy> {
+        let output = self.shell_checked(&dump_command())?.stdout_str();
+        let start = output
+            .find('<')
+            .ok_or_else(|| AGError::Custom(format!("uiautomator dump failed: {}", output.trim())))?;
+        UiHierarchy::parse(&output[start..])
+    }
+
+    /// Bounds of the first element matching `selector` in a fresh dump.
+    pub fn find_element(&mut self, selector: &Selector) -> AGResult<Option<Rect>> {
+        Ok(self.dump_hierarchy()?.find(selector)?.map(|n| n.bounds))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock::MockAdbServer;
+    use super::*;
+    use crate::controller::AdbBuilder;
+
+    const DUMP: &str = r#"<?xml version='1.0' encoding='UTF-8' standalone='yes' ?><hierarchy rotation="1"><node index="0" text="" resource-id="" class="android.widget.FrameLayout" package="com.game" content-desc="" checkable="false" checked="false" clickable="false" enabled="true" focusable="false" focused="false" scrollable="false" long-clickable="false" password="false" selected="false" bounds="[0,0][1920,1080]"><node index="0" text="Start &amp; go" resource-id="com.game:id/start" class="android.widget.Button" package="com.game" content-desc="" clickable="true" enabled="true" bounds="[100,200][300,260]" /><node index="1" text="" resource-id="" class="android.widget.LinearLayout" package="com.game" content-desc="menu" clickable="false" enabled="true" bounds="[0,900][1920,1080]"><node index="0" text="设置" resource-id="com.game:id/settings" class="android.widget.Button" package="com.game" content-desc="" clickable="true" enabled="false" bounds="[10,910][110,1070]" /><node index="1" text="Quit" resource-id="com.game:id/quit" class="android.widget.Button" package="com.game" content-desc="" clickable="true" enabled="true" bounds="[120,910][220,1070]" /></node></node></hierarchy>"#;
+
+    #[test]
+    fn dump_is_parsed_into_a_tree() {
+        let hierarchy = UiHierarchy::parse(DUMP).unwrap();
+        assert_eq!(hierarchy.rotation, 1);
+        assert_eq!(hierarchy.roots.len(), 1);
+        let root = &hierarchy.roots[0];
+        assert_eq!(root.children.len(), 2);
+        let start = &root.children[0];
+        assert_eq!(start.text, "Start & go");
+        assert!(start.clickable);
+        assert_eq!(start.bounds, Rect::new(100, 200, 300, 260));
+        assert_eq!(start.bounds.center(), (200, 230));
+        assert_eq!(root.children[1].children[0].text, "设置");
+        assert_eq!(hierarchy.nodes().len(), 5);
+        assert!(UiHierarchy::parse("<hierarchy><node index=\"0\">").is_err());
+    }
+
+    #[test]
+    fn selectors() {
+        let hierarchy = UiHierarchy::parse(DUMP).unwrap();
+        let bounds = |selector: Selector| -> Vec<Rect> { hierarchy.find_all(&selector).unwrap().iter().map(|n| n.bounds).collect() };
+        let quit = Rect::new(120, 910, 220, 1070);
+        assert_eq!(bounds(Selector::Text("Quit".to_string())), [quit]);
+        assert_eq!(bounds(Selector::ResourceId("quit".to_string())), [quit]);
+        assert_eq!(bounds(Selector::ResourceId("com.game:id/quit".to_string())), [quit]);
+        assert_eq!(bounds(Selector::Class("Button".to_string())).len(), 3);
+        assert_eq!(bounds(Selector::ContentDesc("menu".to_string())).len(), 1);
+        assert_eq!(bounds(Selector::TextContains("go".to_string())), [Rect::new(100, 200, 300, 260)]);
+
+        let path = |p: &str| bounds(Selector::Path(p.to_string()));
+        assert_eq!(path("//android.widget.Button[@text='Quit']"), [quit]);
+        assert_eq!(path("//*[@content-desc=\"menu\"]/Button[2]"), [quit]);
+        assert_eq!(path("/FrameLayout/LinearLayout/Button[@enabled='true']"), [quit]);
+        assert_eq!(path("//Button[contains(@resource-id,'set')]"), [Rect::new(10, 910, 110, 1070)]);
+        assert_eq!(path("/FrameLayout/Button").len(), 1);
+        assert_eq!(path("//node[@clickable='true'][1]").len(), 2);
+        // `[n]` counts among the children of each parent, not across all matches.
+        assert_eq!(path("//Button[1]"), [Rect::new(100, 200, 300, 260), Rect::new(10, 910, 110, 1070)]);
+        assert_eq!(path("//Button[2]"), [quit]);
+        assert_eq!(path("/FrameLayout//Button[2]"), [quit]);
+        assert!(path("/Button").is_empty());
+        for bad in ["Button", "//", "//Button[@text='x'", "//Button[@text=x]"] {
+            assert!(hierarchy.find_all(&Selector::Path(bad.to_string())).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn dump_runs_uiautomator() {
+        let cmd = dump_command();
+        let server = MockAdbServer::start().with_features(&["shell_v2"]).with_reply(&cmd, DUMP);
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).with_target("emulator-5554").build().unwrap();
+        let rect = adb.find_element(&Selector::Text("Quit".to_string())).unwrap();
+        assert_eq!(rect, Some(Rect::new(120, 910, 220, 1070)));
+
+        // Pre-Android 7 devices without shell v2 go through the legacy `shell:` service and its exit marker.
+        let server = MockAdbServer::start().with_reply(&cmd, DUMP);
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).with_target("emulator-5554").build().unwrap();
+        assert_eq!(adb.dump_hierarchy().unwrap().nodes().len(), 5);
+        assert!(server.requests().iter().any(|r| r.starts_with(&format!("shell:{}", cmd))));
+
+        let server = MockAdbServer::start()
+            .with_features(&["shell_v2"])
+            .with_shell_reply(&cmd, "", "ERROR: could not get idle state.\n", 1);
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).with_target("emulator-5554").build().unwrap();
+        assert!(adb.dump_hierarchy().is_err());
+    }
+}
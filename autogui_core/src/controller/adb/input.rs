@@ -0,0 +1,177 @@
+//! Text input, through `input text` for plain ASCII and the ADBKeyboard IME for everything else, and touch gestures
+//! that `input tap` and `input swipe` cannot express.
+use std::time::Duration;
+
+use base64::Engine;
+
+use crate::controller::Gesture;
+use crate::error::{AGError, AGResult};
+
+use super::ADB;
+
+/// ADBKeyboard (https://github.com/senzhk/ADBKeyBoard) commits text it receives through an `ADB_INPUT_B64` broadcast.
+const ADB_KEYBOARD: &str = "com.android.adbkeyboard/.AdbIME";
+
+/// Time for a newly selected IME to bind to the focused field before it can commit text.
+const IME_SWITCH_DELAY: Duration = Duration::from_millis(500);
+
+/// What `input swipe` uses when no duration is given.
+pub(crate) const DEFAULT_SWIPE_DURATION: Duration = Duration::from_millis(300);
+
+/// Intermediate `MOVE` events of a drag. Every `input` call starts a VM, so more would not make the motion smoother.
+const DRAG_STEPS: u32 = 8;
+
+/// Quotes `arg` for the device shell, leaving words that need no quoting as they are.
+pub(crate) fn shell_quote(arg: &str) -> String {
+    let plain = !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "._-/@,+=:%".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+/// Whether `input text` can type `text`: it only maps printable ASCII through the virtual keyboard.
+pub(crate) fn is_plain_text(text: &str) -> bool {
+    text.chars().all(|c| c == ' ' || c.is_ascii_graphic())
+}
+
+/// The `input text` command for plain ASCII `text`. `input` reads `%s` as a space, so a literal `%s` in `text` is
+/// typed as a space too.
+pub(crate) fn input_text_command(text: &str) -> String {
+    format!("input text {}", shell_quote(&text.replace(' ', "%s")))
+}
+
+/// One shell command that holds, moves and releases with `input motionevent` (Android 9+), sleeping in between on the
+/// device so the timing does not depend on round trips.
+pub(crate) fn drag_command(from: (u32, u32), to: (u32, u32), hold: Duration, duration: Duration) -> String {
+    let step = duration / DRAG_STEPS;
+    let mut commands = vec![format!("input motionevent DOWN {} {}", from.0, from.1)];
+    commands.push(format!("sleep {:.3}", hold.as_secs_f64()));
+    for i in 1..=DRAG_STEPS {
+        let lerp = |a: u32, b: u32| (a as i64 + (b as i64 - a as i64) * i as i64 / DRAG_STEPS as i64) as u32;
+        commands.push(format!("input motionevent MOVE {} {}", lerp(from.0, to.0), lerp(from.1, to.1)));
+        commands.push(format!("sleep {:.3}", step.as_secs_f64()));
+    }
+    commands.push(format!("input motionevent UP {} {}", to.0, to.1));
+    commands.join("; ")
+}
+
+/// The first path of `gesture` as `input motionevent` commands.
+pub(crate) fn gesture_command(gesture: &Gesture) -> String {
+    let frames = gesture.frames();
+    let (x, y) = gesture.position(0, 0);
+    let mut commands = vec![format!("input motionevent DOWN {} {}", x, y)];
+    for frame in 1..frames {
+        let (x, y) = gesture.position(0, frame);
+        commands.push(format!("sleep {:.3}", gesture.step().as_secs_f64()));
+        commands.push(format!("input motionevent MOVE {} {}", x, y));
+    }
+    if frames == 1 && !gesture.duration.is_zero() {
+        commands.push(format!("sleep {:.3}", gesture.duration.as_secs_f64()));
+    }
+    let (x, y) = gesture.position(0, frames - 1);
+    commands.push(format!("input motionevent UP {} {}", x, y));
+    commands.join("; ")
+}
+
+impl ADB {
+    /// Types `text` through ADBKeyboard, selecting it for the duration of the call if another IME is active.
+    pub fn input_unicode(&mut self, text: &str) -> AGResult<()> {
+        let current = self.shell_checked("settings get secure default_input_method")?.stdout_str();
+        let current = current.trim();
+        let switch = current != ADB_KEYBOARD;
+        if switch {
+            let output = self.shell_output(&format!("ime enable {0} && ime set {0}", ADB_KEYBOARD))?;
+            if !output.success() || !output.stdout_str().contains("selected") {
+                return Err(AGError::Custom(format!(
+                    "typing non-ASCII text needs the ADBKeyboard IME ({}) installed",
+                    ADB_KEYBOARD
+                )));
+            }
+            std::thread::sleep(IME_SWITCH_DELAY);
+        }
+        let encoded = base64::engine::general_purpose::STANDARD.encode(text);
+        let result = self.shell_checked(&format!("am broadcast -a ADB_INPUT_B64 --es msg {}", encoded));
+        if switch && !current.is_empty() && current != "null" {
+            self.shell_checked(&format!("ime set {}", shell_quote(current)))?;
+        }
+        result.map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock::MockAdbServer;
+    use super::*;
+    use crate::controller::{AdbBuilder, Controller};
+
+    #[test]
+    fn text_is_escaped() {
+        assert_eq!(input_text_command("hello"), "input text hello");
+        assert_eq!(input_text_command("a b&c"), "input text 'a%sb&c'");
+        assert_eq!(input_text_command("it's; rm -rf /"), r"input text 'it'\''s;%srm%s-rf%s/'");
+        assert_eq!(shell_quote(""), "''");
+        assert!(is_plain_text("~!@#$%^&*()_+ `\"<>?"));
+        assert!(!is_plain_text("你好"));
+        assert!(!is_plain_text("line\nbreak"));
+    }
+
+    #[test]
+    fn drag_holds_then_moves() {
+        let command = drag_command((100, 500), (900, 100), Duration::from_millis(600), Duration::from_millis(400));
+        let parts: Vec<_> = command.split("; ").collect();
+        assert_eq!(
+            parts[..4],
+            [
+                "input motionevent DOWN 100 500",
+                "sleep 0.600",
+                "input motionevent MOVE 200 450",
+                "sleep 0.050"
+            ]
+        );
+        assert_eq!(
+            parts[parts.len() - 3..],
+            ["input motionevent MOVE 900 100", "sleep 0.050", "input motionevent UP 900 100"]
+        );
+        assert_eq!(parts.len(), 3 + 2 * DRAG_STEPS as usize);
+    }
+
+    #[test]
+    fn unicode_goes_through_ime() {
+        let latin = "com.google.android.inputmethod.latin/com.android.inputmethod.latin.LatinIME";
+        let server = MockAdbServer::start()
+            .with_features(&["shell_v2"])
+            .with_reply("settings get secure default_input_method", format!("{}\n", latin))
+            .with_reply(
+                &format!("ime enable {0} && ime set {0}", ADB_KEYBOARD),
+                format!("Input method {} selected for user #0\n", ADB_KEYBOARD),
+            );
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).with_target("emulator-5554").build().unwrap();
+        adb.input_text("a b").unwrap();
+        adb.input_text("你好 world").unwrap();
+        let shells: Vec<_> = server
+            .requests()
+            .into_iter()
+            .filter_map(|r| r.strip_prefix("shell,v2,raw:").map(String::from))
+            .collect();
+        assert_eq!(shells[0], "input text a%sb");
+        assert_eq!(shells[3], "am broadcast -a ADB_INPUT_B64 --es msg 5L2g5aW9IHdvcmxk");
+        assert_eq!(shells[4], format!("ime set {}", latin));
+    }
+
+    #[test]
+    fn missing_ime_is_reported() {
+        let server = MockAdbServer::start()
+            .with_features(&["shell_v2"])
+            .with_reply("settings get secure default_input_method", "null\n")
+            .with_shell_reply(
+                &format!("ime enable {0} && ime set {0}", ADB_KEYBOARD),
+                "",
+                format!("Unknown input method {} cannot be enabled for user #0\n", ADB_KEYBOARD),
+                255,
+            );
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).with_target("emulator-5554").build().unwrap();
+        assert!(matches!(adb.input_text("é"), Err(AGError::Custom(message)) if message.contains("ADBKeyboard")));
+    }
+}
@@ -0,0 +1,314 @@
+//! Streaming `logcat -v threadtime`, parsed into `LogEntry`s.
+use std::io::BufRead;
+use std::time::{Duration, Instant};
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+
+use crate::error::{AGError, AGResult};
+
+use super::input::shell_quote;
+use super::{ShellStream, ADB};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LogLevel {
+    Verbose,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Fatal,
+}
+
+impl LogLevel {
+    pub fn from_char(c: char) -> Option<Self> {
+        match c {
+            'V' => Some(LogLevel::Verbose),
+            'D' => Some(LogLevel::Debug),
+            'I' => Some(LogLevel::Info),
+            'W' => Some(LogLevel::Warn),
+            'E' => Some(LogLevel::Error),
+            'F' | 'A' => Some(LogLevel::Fatal),
+            _ => None,
+        }
+    }
+
+    pub fn as_char(self) -> char {
+        match self {
+            LogLevel::Verbose => 'V',
+            LogLevel::Debug => 'D',
+            LogLevel::Info => 'I',
+            LogLevel::Warn => 'W',
+            LogLevel::Error => 'E',
+            LogLevel::Fatal => 'F',
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+    /// Device local time. `threadtime` has no year, so the host's current year is assumed.
+    pub timestamp: NaiveDateTime,
+    pub pid: u32,
+    pub tid: u32,
+    pub level: LogLevel,
+    pub tag: String,
+    pub message: String,
+}
+
+/// `threadtime` lines carry no year. Takes the latest year that puts the date no later than the day after `today`,
+/// so December lines read in January stay in the old year and Feb 29 lands in a leap year.
+fn entry_date(month: u32, day: u32, today: NaiveDate) -> Option<NaiveDate> {
+    (0..8)
+        .filter_map(|back| NaiveDate::from_ymd_opt(today.year() - back, month, day))
+        .find(|date| *date <= today + chrono::Days::new(1))
+}
+
+impl LogEntry {
+    /// Parses a `threadtime` line such as `05-21 10:42:03.123  1234  1250 I ActivityManager: Start proc`.
+    /// Returns `None` for anything else, e.g. `--------- beginning of main`.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(6, char::is_whitespace).filter(|f| !f.is_empty());
+        let (date, time) = (fields.next()?, fields.next()?);
+        let (month, day) = date.split_once('-')?;
+        let date = entry_date(month.parse().ok()?, day.parse().ok()?, chrono::Local::now().date_naive())?;
+        let timestamp = date.and_time(time.parse().ok()?);
+        // Padding makes the field count vary, so split the rest by hand.
+        let rest = line.get(line.find(time)? + time.len()..)?;
+        let mut words = rest.split_whitespace();
+        let pid = words.next()?.parse().ok()?;
+        let tid = words.next()?.parse().ok()?;
+        let level_char = words.next().filter(|l| l.len() == 1)?.chars().next()?;
+        let level = LogLevel::from_char(level_char)?;
+        // Search for the letter as written: `A` (assert) parses to `Fatal`, which prints as `F`.
+        let after_level = rest.get(rest.find(&format!(" {} ", level_char))? + 3..)?;
+        let (tag, message) = after_level.split_once(": ").unwrap_or((after_level.trim_end_matches(':'), ""));
+        Some(LogEntry {
+            timestamp,
+            pid,
+            tid,
+            level,
+            tag: tag.trim().to_string(),
+            message: message.trim_end_matches(['\r', '\n']).to_string(),
+        })
+    }
+}
+
+/// What `ADB::logcat` asks for. Tags and level are filtered on the device; `matches` applies the same rules on the
+/// host.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogcatFilter {
+    level: Option<LogLevel>,
+    tags: Vec<String>,
+    pid: Option<u32>,
+    since_now: bool,
+}
+
+impl LogcatFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drops entries below `level`.
+    pub fn with_level(mut self, level: LogLevel) -> Self {
+        self.level = Some(level);
+        self
+    }
+
+    /// Keeps only entries with one of the tags given so far.
+    pub fn with_tag(mut self, tag: &str) -> Self {
+        self.tags.push(tag.to_string());
+        self
+    }
+
+    /// Keeps only entries of one process. Needs `logcat --pid`, Android 7 and later.
+    pub fn with_pid(mut self, pid: u32) -> Self {
+        self.pid = Some(pid);
+        self
+    }
+
+    /// Skips the backlog and starts at the device's current second.
+    pub fn since_now(mut self) -> Self {
+        self.since_now = true;
+        self
+    }
+
+    pub fn matches(&self, entry: &LogEntry) -> bool {
+        self.level.is_none_or(|level| entry.level >= level)
+            && (self.tags.is_empty() || self.tags.contains(&entry.tag))
+            && self.pid.is_none_or(|pid| entry.pid == pid)
+    }
+
+    fn command(&self) -> String {
+        let mut cmd = "logcat -v threadtime".to_string();
+        if self.since_now {
+            cmd += " -T \"$(date '+%m-%d %H:%M:%S.000')\"";
+        }
+        if let Some(pid) = self.pid {
+            cmd += &format!(" --pid={}", pid);
+        }
+        let level = self.level.unwrap_or(LogLevel::Verbose).as_char();
+        if self.tags.is_empty() {
+            cmd += &format!(" {}", shell_quote(&format!("*:{}", level)));
+        } else {
+            for tag in &self.tags {
+                cmd += &format!(" {}", shell_quote(&format!("{}:{}", tag, level)));
+            }
+            cmd += " '*:S'";
+        }
+        cmd
+    }
+}
+
+/// A running `logcat`, yielding the entries that pass its filter until the stream ends. Dropping it stops `logcat`.
+pub struct Logcat {
+    stream: ShellStream,
+    filter: LogcatFilter,
+    /// Bytes of a line cut off by a read timeout.
+    pending: Vec<u8>,
+}
+
+impl Logcat {
+    /// The next matching entry. `Ok(None)` once `logcat` exits; read timeouts come back as `AGError::Io`.
+    pub fn next_entry(&mut self) -> AGResult<Option<LogEntry>> {
+        loop {
+            if self.stream.read_until(b'\n', &mut self.pending)? == 0 {
+                return Ok(None);
+            }
+            if self.pending.last() != Some(&b'\n') {
+                continue;
+            }
+            let line = String::from_utf8_lossy(&std::mem::take(&mut self.pending)).to_string();
+            match LogEntry::parse(&line) {
+                Some(entry) if self.filter.matches(&entry) => return Ok(Some(entry)),
+                _ => continue,
+            }
+        }
+    }
+
+    /// Reads until an entry satisfies `predicate` and returns it, or fails with `AGError::Timeout` after `timeout`.
+    /// The stream's read timeout is restored afterwards.
+    pub fn wait_for(&mut self, mut predicate: impl FnMut(&LogEntry) -> bool, timeout: Duration) -> AGResult<LogEntry> {
+        let deadline = Instant::now() + timeout;
+        let previous = self.stream.read_timeout()?;
+        let result = (|| loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(AGError::Timeout {
+                    serial: String::new(),
+                    service: "logcat".to_string(),
+                });
+            }
+            self.stream.set_read_timeout(Some(remaining))?;
+            match self.next_entry() {
+                Ok(Some(entry)) if predicate(&entry) => return Ok(entry),
+                Ok(Some(_)) => {}
+                Ok(None) => return Err(AGError::Custom("logcat exited".to_string())),
+                Err(e) => return Err(e.in_service("", "logcat")),
+            }
+        })();
+        self.stream.set_read_timeout(previous)?;
+        result
+    }
+}
+
+/// Ends at the first error as well as when `logcat` exits; use `next_entry` to tell them apart, e.g. after setting a
+/// read timeout.
+impl Iterator for Logcat {
+    type Item = LogEntry;
+
+    fn next(&mut self) -> Option<LogEntry> {
+        self.next_entry().ok().flatten()
+    }
+}
+
+impl ADB {
+    /// Starts `logcat -v threadtime` on a stream of its own; the `ADB` stays usable meanwhile.
+    pub fn logcat(&mut self, filter: LogcatFilter) -> AGResult<Logcat> {
+        let stream = self.shell_stream(&filter.command())?;
+        Ok(Logcat {
+            stream,
+            filter,
+            pending: Vec::new(),
+        })
+    }
+
+    /// Waits for a new entry passing `filter` whose message contains `text`, e.g. to sync a script with an app
+    /// finishing a loading step.
+    pub fn wait_for_log(&mut self, filter: LogcatFilter, text: &str, timeout: Duration) -> AGResult<LogEntry> {
+        let serial = self.target.clone();
+        let mut logcat = self.logcat(filter.since_now())?;
+        logcat.wait_for(|e| e.message.contains(text), timeout).map_err(|e| match e {
+            AGError::Timeout { service, .. } => AGError::Timeout { serial, service },
+            other => other,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock::MockAdbServer;
+    use super::*;
+    use crate::controller::AdbBuilder;
+
+    const LOG: &str = "--------- beginning of main\n\
+        05-21 10:42:03.123  1234  1250 I ActivityManager: Start proc 4321:com.game/u0a123\n\
+        05-21 10:42:03.500  4321  4321 D Unity   : Loading scene: Battle\n\
+        05-21 10:42:04.001  4321  4399 E Unity   : NullReferenceException: boom\n\
+        05-21 10:42:04.002   321   321 W : empty tag: still parsed\n";
+
+    #[test]
+    fn lines_are_parsed() {
+        let entries: Vec<_> = LOG.lines().filter_map(LogEntry::parse).collect();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0].pid, 1234);
+        assert_eq!(entries[0].tid, 1250);
+        assert_eq!(entries[0].level, LogLevel::Info);
+        assert_eq!(entries[0].tag, "ActivityManager");
+        assert_eq!(entries[0].message, "Start proc 4321:com.game/u0a123");
+        assert_eq!(entries[0].timestamp.format("%m-%d %H:%M:%S%.3f").to_string(), "05-21 10:42:03.123");
+        assert_eq!(entries[2].message, "NullReferenceException: boom");
+        assert_eq!(entries[3].tag, "");
+
+        let today = NaiveDate::from_ymd_opt(2026, 1, 2).unwrap();
+        assert_eq!(entry_date(12, 31, today), NaiveDate::from_ymd_opt(2025, 12, 31));
+        assert_eq!(entry_date(1, 3, today), NaiveDate::from_ymd_opt(2026, 1, 3));
+        assert_eq!(entry_date(2, 29, today), NaiveDate::from_ymd_opt(2024, 2, 29));
+        assert_eq!(entry_date(2, 30, today), None);
+
+        let assert = LogEntry::parse("05-21 10:42:05.000  4321  4321 A libc    : Fatal signal 6 (SIGABRT)").unwrap();
+        assert_eq!(assert.level, LogLevel::Fatal);
+        assert_eq!(assert.tag, "libc");
+        assert_eq!(assert.message, "Fatal signal 6 (SIGABRT)");
+
+        let filter = LogcatFilter::new().with_tag("Unity").with_level(LogLevel::Warn);
+        assert_eq!(entries.iter().filter(|e| filter.matches(e)).count(), 1);
+        assert_eq!(filter.command(), "logcat -v threadtime Unity:W '*:S'");
+        assert_eq!(
+            LogcatFilter::new().with_pid(7).since_now().command(),
+            "logcat -v threadtime -T \"$(date '+%m-%d %H:%M:%S.000')\" --pid=7 '*:V'"
+        );
+    }
+
+    #[test]
+    fn entries_are_streamed_and_awaited() {
+        let filter = LogcatFilter::new().with_tag("Unity");
+        let server = MockAdbServer::start()
+            .with_reply(&filter.command(), LOG)
+            .with_endless_reply(&filter.clone().since_now().command(), "05-21 10:42:05.000  4321  4321 D Unity   : idle\n");
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).with_target("emulator-5554").build().unwrap();
+        let messages: Vec<_> = adb.logcat(filter.clone()).unwrap().map(|e| e.message).collect();
+        assert_eq!(messages, ["Loading scene: Battle", "NullReferenceException: boom"]);
+
+        let mut logcat = adb.logcat(filter.clone()).unwrap();
+        let error = logcat.wait_for(|e| e.level == LogLevel::Error, Duration::from_secs(5)).unwrap();
+        assert_eq!(error.tid, 4399);
+        assert_eq!(logcat.stream.read_timeout().unwrap(), None);
+
+        let entry = adb.wait_for_log(filter.clone(), "idle", Duration::from_secs(5)).unwrap();
+        assert_eq!(entry.message, "idle");
+        assert!(matches!(
+            adb.wait_for_log(filter, "never", Duration::from_millis(100)),
+            Err(AGError::Timeout { serial, .. }) if serial == "emulator-5554"
+        ));
+    }
+}
@@ -0,0 +1,270 @@
+//! Client for minicap's frame stream: a 24-byte banner, then JPEG frames each prefixed with their length. A reader
+//! thread keeps only the newest frame, which is decoded when a screenshot is asked for.
+use std::io::Read;
+use std::net::{Shutdown, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use image::RgbaImage;
+
+use crate::error::{AGError, AGResult};
+
+use super::{ForwardSpec, ADB};
+
+/// How long `screenshot` waits for the first frame; minicap only sends one once the screen changed or on connect.
+const FIRST_FRAME_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Frames larger than this are treated as a corrupt stream rather than allocated.
+const MAX_FRAME_SIZE: usize = 64 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MinicapBanner {
+    pub version: u8,
+    pub pid: u32,
+    /// Display size in the natural orientation.
+    pub real_size: (u32, u32),
+    /// Size the frames are scaled to.
+    pub virtual_size: (u32, u32),
+    /// Display rotation in degrees when the stream started.
+    pub orientation: u32,
+    /// `QUIRK_*` bits.
+    pub quirks: u8,
+}
+
+impl MinicapBanner {
+    /// Frames only arrive when the screen changed.
+    pub const QUIRK_DUMB: u8 = 1;
+    /// Frames stay in the natural orientation when the display rotates.
+    pub const QUIRK_ALWAYS_UPRIGHT: u8 = 2;
+    /// Frames may show tearing.
+    pub const QUIRK_TEAR: u8 = 4;
+
+    /// Parses the fixed part of the banner; byte 1 holds its full length, which later versions may extend.
+    pub fn parse(data: &[u8]) -> AGResult<Self> {
+        let data = data.get(..24).ok_or(AGError::Decode)?;
+        let u32_at = |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        Ok(MinicapBanner {
+            version: data[0],
+            pid: u32_at(2),
+            real_size: (u32_at(6), u32_at(10)),
+            virtual_size: (u32_at(14), u32_at(18)),
+            orientation: data[22] as u32 * 90,
+            quirks: data[23],
+        })
+    }
+
+    fn read(stream: &mut impl Read) -> AGResult<Self> {
+        let mut head = [0; 2];
+        stream.read_exact(&mut head)?;
+        let mut banner = vec![0; (head[1] as usize).max(2)];
+        banner[..2].copy_from_slice(&head);
+        stream.read_exact(&mut banner[2..])?;
+        Self::parse(&banner)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Latest {
+    jpeg: Option<Vec<u8>>,
+    count: u64,
+    /// Why the reader thread stopped.
+    error: Option<String>,
+}
+
+type Shared = Arc<(Mutex<Latest>, Condvar)>;
+
+fn read_frames(mut stream: impl Read, shared: &Shared) {
+    let error = loop {
+        let mut len = [0; 4];
+        if let Err(e) = stream.read_exact(&mut len) {
+            break e.to_string();
+        }
+        let len = u32::from_le_bytes(len) as usize;
+        if len > MAX_FRAME_SIZE {
+            break format!("frame of {} bytes", len);
+        }
+        let mut jpeg = vec![0; len];
+        if let Err(e) = stream.read_exact(&mut jpeg) {
+            break e.to_string();
+        }
+        let mut latest = shared.0.lock().unwrap();
+        latest.jpeg = Some(jpeg);
+        latest.count += 1;
+        shared.1.notify_all();
+    };
+    shared.0.lock().unwrap().error = Some(error);
+    shared.1.notify_all();
+}
+
+#[derive(Debug)]
+pub struct Minicap {
+    banner: MinicapBanner,
+    latest: Shared,
+    /// Shut down on drop to stop the reader thread.
+    socket: Option<TcpStream>,
+}
+
+impl Minicap {
+    /// Reads the banner from `stream`, then keeps reading frames on a background thread until it ends.
+    pub fn new(stream: impl Read + Send + 'static) -> AGResult<Self> {
+        Self::start(stream, None)
+    }
+
+    pub fn connect(addr: impl std::net::ToSocketAddrs) -> AGResult<Self> {
+        let stream = TcpStream::connect(addr)?;
+        let socket = stream.try_clone()?;
+        Self::start(stream, Some(socket))
+    }
+
+    fn start(mut stream: impl Read + Send + 'static, socket: Option<TcpStream>) -> AGResult<Self> {
+        let banner = MinicapBanner::read(&mut stream)?;
+        let latest: Shared = Arc::default();
+        let shared = latest.clone();
+        thread::spawn(move || read_frames(stream, &shared));
+        Ok(Minicap { banner, latest, socket })
+    }
+
+    pub fn banner(&self) -> &MinicapBanner {
+        &self.banner
+    }
+
+    /// Frames received so far.
+    pub fn frame_count(&self) -> u64 {
+        self.latest.0.lock().unwrap().count
+    }
+
+    /// The newest frame as sent, waiting up to `timeout` for the first one.
+    pub fn latest_jpeg(&self, timeout: Duration) -> AGResult<Vec<u8>> {
+        let (latest, _) = self
+            .latest
+            .1
+            .wait_timeout_while(self.latest.0.lock().unwrap(), timeout, |l| l.jpeg.is_none() && l.error.is_none())
+            .unwrap();
+        if let Some(error) = &latest.error {
+            return Err(AGError::Custom(format!("minicap stream ended: {}", error)));
+        }
+        latest.jpeg.clone().ok_or(AGError::Timeout {
+            serial: "minicap".to_string(),
+            service: "first frame".to_string(),
+        })
+    }
+
+    /// The newest frame, decoded. Frames do not queue up, so this never returns a stale one while newer ones wait.
+    pub fn screenshot(&self) -> AGResult<RgbaImage> {
+        let jpeg = self.latest_jpeg(FIRST_FRAME_TIMEOUT)?;
+        Ok(image::load_from_memory_with_format(&jpeg, image::ImageFormat::Jpeg)?.to_rgba8())
+    }
+}
+
+impl Drop for Minicap {
+    fn drop(&mut self) {
+        if let Some(socket) = &self.socket {
+            let _ = socket.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+impl ADB {
+    /// Connects to a minicap agent listening on `localabstract:<socket>` through a forward. `screenshot` returns its
+    /// latest frame afterwards instead of running `screencap`.
+    pub fn enable_minicap(&mut self, socket: &str) -> AGResult<()> {
+        let remote = ForwardSpec::LocalAbstract(socket.to_string());
+        let ForwardSpec::Tcp(port) = self.forward(&ForwardSpec::Tcp(0), &remote)? else {
+            return Err(AGError::Decode);
+        };
+        self.minicap = Some(Minicap::connect((self.stream.peer_addr()?.ip(), port))?);
+        Ok(())
+    }
+
+    /// Goes back to `screencap`.
+    pub fn disable_minicap(&mut self) {
+        self.minicap = None;
+    }
+
+    pub fn minicap(&self) -> Option<&Minicap> {
+        self.minicap.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    fn banner(width: u32, height: u32) -> Vec<u8> {
+        let mut banner = vec![1, 24];
+        for value in [4242, width, height, width / 2, height / 2] {
+            banner.extend_from_slice(&value.to_le_bytes());
+        }
+        banner.extend_from_slice(&[1, MinicapBanner::QUIRK_TEAR]);
+        banner
+    }
+
+    fn frame(color: [u8; 3]) -> Vec<u8> {
+        let img = image::RgbImage::from_pixel(8, 4, image::Rgb(color));
+        let mut jpeg = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, 95)
+            .encode_image(&img)
+            .unwrap();
+        let mut framed = (jpeg.len() as u32).to_le_bytes().to_vec();
+        framed.extend(jpeg);
+        framed
+    }
+
+    /// Serves `recording` to the first client and keeps the socket open until the client goes away.
+    fn replay(recording: Vec<u8>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(&recording).unwrap();
+            let _ = stream.read(&mut [0]);
+        });
+        addr
+    }
+
+    #[test]
+    fn banner_is_parsed() {
+        let parsed = MinicapBanner::parse(&banner(1080, 1920)).unwrap();
+        assert_eq!(
+            parsed,
+            MinicapBanner {
+                version: 1,
+                pid: 4242,
+                real_size: (1080, 1920),
+                virtual_size: (540, 960),
+                orientation: 90,
+                quirks: MinicapBanner::QUIRK_TEAR,
+            }
+        );
+        assert!(MinicapBanner::parse(&banner(1, 1)[..20]).is_err());
+    }
+
+    #[test]
+    fn latest_frame_is_returned() {
+        let mut recording = banner(8, 4);
+        for color in [[255, 0, 0], [0, 255, 0], [0, 0, 255]] {
+            recording.extend(frame(color));
+        }
+        let minicap = Minicap::connect(replay(recording)).unwrap();
+        assert_eq!(minicap.banner().real_size, (8, 4));
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while minicap.frame_count() < 3 && std::time::Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let img = minicap.screenshot().unwrap();
+        assert_eq!(img.dimensions(), (8, 4));
+        let [r, g, b, a] = img.get_pixel(3, 2).0;
+        assert!(r < 16 && g < 16 && b > 240 && a == 255, "{:?}", img.get_pixel(3, 2));
+    }
+
+    #[test]
+    fn truncated_stream_is_an_error() {
+        let mut recording = banner(8, 4);
+        recording.extend(&frame([0, 0, 0])[..10]);
+        let minicap = Minicap::new(std::io::Cursor::new(recording)).unwrap();
+        assert!(matches!(minicap.screenshot(), Err(AGError::Custom(message)) if message.contains("minicap")));
+    }
+}
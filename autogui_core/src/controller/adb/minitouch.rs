@@ -0,0 +1,379 @@
+//! Client for the minitouch text protocol, which maatouch speaks too: `d`/`m`/`u` per contact, `c` to commit a frame
+//! and `w` to wait on the device, over a socket forwarded to the agent.
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use crate::controller::Gesture;
+use crate::error::{AGError, AGResult};
+
+use super::{DisplayInfo, ForwardSpec, ADB};
+
+/// Pressure of every contact, clamped to what the device reports.
+const DEFAULT_PRESSURE: u32 = 50;
+
+/// The lines the agent sends on connect: `v <version>`, `^ <contacts> <max x> <max y> <max pressure>`, `$ <pid>`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MinitouchBanner {
+    pub version: u32,
+    pub max_contacts: u32,
+    pub max_x: u32,
+    pub max_y: u32,
+    pub max_pressure: u32,
+    pub pid: Option<u32>,
+}
+
+impl MinitouchBanner {
+    /// Reads banner lines up to and including the `$` line. Reads byte by byte so nothing after it is consumed.
+    fn read(stream: &mut impl Read) -> AGResult<Self> {
+        let mut banner = MinitouchBanner::default();
+        let mut limits = false;
+        loop {
+            let mut line = Vec::new();
+            let mut byte = [0];
+            while stream.read(&mut byte)? == 1 && byte[0] != b'\n' {
+                line.push(byte[0]);
+            }
+            if line.is_empty() {
+                return if limits { Ok(banner) } else { Err(AGError::Decode) };
+            }
+            let line = String::from_utf8_lossy(&line).to_string();
+            let mut fields = line.split_whitespace();
+            let kind = fields.next();
+            let numbers: Vec<u32> = fields.map(|f| f.parse().map_err(|_| AGError::Decode)).collect::<AGResult<_>>()?;
+            match (kind, numbers.as_slice()) {
+                (Some("v"), &[version]) => banner.version = version,
+                (Some("^"), &[max_contacts, max_x, max_y, max_pressure]) => {
+                    banner = MinitouchBanner {
+                        max_contacts,
+                        max_x,
+                        max_y,
+                        max_pressure,
+                        ..banner
+                    };
+                    limits = true;
+                }
+                (Some("$"), &[pid]) if limits => {
+                    banner.pid = Some(pid);
+                    return Ok(banner);
+                }
+                _ => return Err(AGError::Decode),
+            }
+        }
+    }
+}
+
+/// A connected minitouch agent. Takes screen coordinates and maps them to the touch panel, which stays in the
+/// natural orientation when the display rotates.
+#[derive(Debug)]
+pub struct Minitouch<S = TcpStream> {
+    stream: S,
+    banner: MinitouchBanner,
+    display: DisplayInfo,
+}
+
+impl<S: Read + Write> Minitouch<S> {
+    pub fn new(mut stream: S, display: DisplayInfo) -> AGResult<Self> {
+        let banner = MinitouchBanner::read(&mut stream)?;
+        Ok(Minitouch { stream, banner, display })
+    }
+
+    pub fn banner(&self) -> &MinitouchBanner {
+        &self.banner
+    }
+
+    /// Call after the display rotated or was resized so coordinates keep mapping to the right spot.
+    pub fn set_display(&mut self, display: DisplayInfo) {
+        self.display = display;
+    }
+
+    fn to_touch(&self, (x, y): (u32, u32)) -> (u32, u32) {
+        let (w, h) = self.display.effective_size();
+        let (px, py) = (x as f64 / w.max(1) as f64, y as f64 / h.max(1) as f64);
+        let (nx, ny) = match self.display.rotation % 4 {
+            0 => (px, py),
+            1 => (1.0 - py, px),
+            2 => (1.0 - px, 1.0 - py),
+            _ => (py, 1.0 - px),
+        };
+        let scale = |n: f64, max: u32| ((n.clamp(0.0, 1.0) * max as f64).round() as u32).min(max);
+        (scale(nx, self.banner.max_x), scale(ny, self.banner.max_y))
+    }
+
+    fn pressure(&self) -> u32 {
+        DEFAULT_PRESSURE.min(self.banner.max_pressure)
+    }
+
+    fn send(&mut self, commands: &str) -> AGResult<()> {
+        self.stream.write_all(commands.as_bytes())?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    pub fn down(&mut self, contact: u32, x: u32, y: u32) -> AGResult<()> {
+        let (x, y) = self.to_touch((x, y));
+        self.send(&format!("d {} {} {} {}\n", contact, x, y, self.pressure()))
+    }
+
+    pub fn move_to(&mut self, contact: u32, x: u32, y: u32) -> AGResult<()> {
+        let (x, y) = self.to_touch((x, y));
+        self.send(&format!("m {} {} {} {}\n", contact, x, y, self.pressure()))
+    }
+
+    pub fn up(&mut self, contact: u32) -> AGResult<()> {
+        self.send(&format!("u {}\n", contact))
+    }
+
+    /// Applies the `d`/`m`/`u` commands sent since the last commit as one frame.
+    pub fn commit(&mut self) -> AGResult<()> {
+        self.send("c\n")
+    }
+
+    /// Makes the agent pause before reading further commands, which keeps timing exact regardless of the link.
+    pub fn wait(&mut self, duration: Duration) -> AGResult<()> {
+        self.send(&format!("w {}\n", duration.as_millis()))
+    }
+
+    /// Lifts every contact.
+    pub fn reset(&mut self) -> AGResult<()> {
+        self.send("r\n")
+    }
+
+    /// Commands for `gesture`: every finger down in the first frame, one committed frame per step, then all up.
+    fn gesture_commands(&self, gesture: &Gesture) -> AGResult<String> {
+        if gesture.paths().len() > self.banner.max_contacts as usize {
+            return Err(AGError::Custom(format!(
+                "gesture needs {} contacts, the device supports {}",
+                gesture.paths().len(),
+                self.banner.max_contacts
+            )));
+        }
+        let pressure = self.pressure();
+        let fingers = 0..gesture.paths().len();
+        let mut commands = String::new();
+        for frame in 0..gesture.frames() {
+            if frame > 0 {
+                commands += &format!("w {}\n", gesture.step().as_millis());
+            }
+            for finger in fingers.clone() {
+                let (x, y) = self.to_touch(gesture.position(finger, frame));
+                let kind = if frame == 0 { 'd' } else { 'm' };
+                commands += &format!("{} {} {} {} {}\n", kind, finger, x, y, pressure);
+            }
+            commands += "c\n";
+        }
+        if gesture.frames() == 1 && !gesture.duration.is_zero() {
+            commands += &format!("w {}\n", gesture.duration.as_millis());
+        }
+        for finger in fingers {
+            commands += &format!("u {}\n", finger);
+        }
+        commands += "c\n";
+        Ok(commands)
+    }
+
+    /// Sends the whole gesture in one write; the agent does the timing.
+    pub fn perform(&mut self, gesture: &Gesture) -> AGResult<()> {
+        if gesture.paths().is_empty() {
+            return Ok(());
+        }
+        let commands = self.gesture_commands(gesture)?;
+        self.send(&commands)
+    }
+
+    pub fn tap(&mut self, x: u32, y: u32) -> AGResult<()> {
+        self.perform(&Gesture::tap(x, y))
+    }
+
+    /// Touches `from`, holds for `hold`, then moves to `to` in frames of about 16 ms and lifts. Sent in one write.
+    pub fn drag(&mut self, from: (u32, u32), to: (u32, u32), hold: Duration, duration: Duration) -> AGResult<()> {
+        let steps = (duration.as_millis() / 16).max(1) as i64;
+        let pressure = self.pressure();
+        let (x, y) = self.to_touch(from);
+        let mut commands = format!("d 0 {} {} {}\nc\n", x, y, pressure);
+        if !hold.is_zero() {
+            commands += &format!("w {}\n", hold.as_millis());
+        }
+        for i in 1..=steps {
+            let lerp = |a: u32, b: u32| (a as i64 + (b as i64 - a as i64) * i / steps) as u32;
+            let (x, y) = self.to_touch((lerp(from.0, to.0), lerp(from.1, to.1)));
+            commands += &format!("w {}\nm 0 {} {} {}\nc\n", (duration / steps as u32).as_millis(), x, y, pressure);
+        }
+        commands += "u 0\nc\n";
+        self.send(&commands)
+    }
+
+    pub fn swipe(&mut self, from: (u32, u32), to: (u32, u32), duration: Duration) -> AGResult<()> {
+        self.drag(from, to, Duration::ZERO, duration)
+    }
+}
+
+impl ADB {
+    /// Connects to a minitouch or maatouch agent listening on `localabstract:<socket>` through a forward, e.g. after
+    /// starting `/data/local/tmp/minitouch` with `shell_stream`. Taps, swipes, drags and gestures go through it afterwards.
+    pub fn enable_minitouch(&mut self, socket: &str) -> AGResult<()> {
+        let remote = ForwardSpec::LocalAbstract(socket.to_string());
+        let ForwardSpec::Tcp(port) = self.forward(&ForwardSpec::Tcp(0), &remote)? else {
+            return Err(AGError::Decode);
+        };
+        let stream = TcpStream::connect((self.stream.peer_addr()?.ip(), port))?;
+        stream.set_read_timeout(self.stream.read_timeout()?)?;
+        let display = self.display_info()?;
+        self.minitouch = Some(Minitouch::new(stream, display)?);
+        Ok(())
+    }
+
+    /// Goes back to `input` commands.
+    pub fn disable_minitouch(&mut self) {
+        self.minitouch = None;
+    }
+
+    /// The enabled minitouch, updated to the current display first since touches are mapped through its rotation.
+    pub(crate) fn oriented_minitouch(&mut self) -> AGResult<Option<&mut Minitouch>> {
+        if self.minitouch.is_none() {
+            return Ok(None);
+        }
+        let display = self.display_info()?;
+        if let Some(minitouch) = self.minitouch.as_mut() {
+            minitouch.set_display(display);
+            return Ok(Some(minitouch));
+        }
+        Ok(None)
+    }
+
+    pub fn minitouch(&mut self) -> Option<&mut Minitouch> {
+        self.minitouch.as_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::display::ROTATION_COMMAND;
+    use super::super::mock::MockAdbServer;
+    use super::*;
+    use crate::controller::{AdbBuilder, Controller};
+    use std::net::TcpListener;
+    use std::thread;
+
+    const DISPLAY: DisplayInfo = DisplayInfo {
+        physical: (1000, 2000),
+        override_size: None,
+        rotation: 0,
+    };
+
+    /// An agent that sends `banner` and records everything it receives until the client disconnects.
+    fn recording_agent(banner: &'static str) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(banner.as_bytes()).unwrap();
+            let mut received = String::new();
+            stream.read_to_string(&mut received).unwrap();
+            received
+        });
+        (addr, handle)
+    }
+
+    #[test]
+    fn banner_and_coordinates() {
+        let banner = MinitouchBanner::read(&mut &b"v 1\n^ 10 4000 4000 255\n$ 4321\n"[..]).unwrap();
+        assert_eq!(
+            banner,
+            MinitouchBanner {
+                version: 1,
+                max_contacts: 10,
+                max_x: 4000,
+                max_y: 4000,
+                max_pressure: 255,
+                pid: Some(4321)
+            }
+        );
+        assert!(MinitouchBanner::read(&mut &b"v 1\n"[..]).is_err());
+        assert!(MinitouchBanner::read(&mut &b"garbage\n"[..]).is_err());
+
+        let stream = std::io::Cursor::new(b"v 1\n^ 2 4000 4000 0\n$ 1\n".to_vec());
+        let mut touch = Minitouch::new(stream, DISPLAY).unwrap();
+        assert_eq!(touch.to_touch((250, 500)), (1000, 1000));
+        touch.set_display(DisplayInfo { rotation: 1, ..DISPLAY });
+        assert_eq!(touch.to_touch((0, 0)), (4000, 0));
+        assert_eq!(touch.to_touch((2000, 1000)), (0, 4000));
+        assert_eq!(touch.pressure(), 0);
+    }
+
+    #[test]
+    fn gestures_are_sent_as_frames() {
+        let (addr, agent) = recording_agent("v 1\n^ 2 4000 4000 255\n$ 99\n");
+        let mut touch = Minitouch::new(TcpStream::connect(addr).unwrap(), DISPLAY).unwrap();
+        touch.tap(100, 100).unwrap();
+        let pinch = Gesture::pinch((500, 1000), 400, 200, Duration::from_millis(100));
+        touch.perform(&pinch).unwrap();
+        let three = Gesture::new(Duration::ZERO)
+            .with_path(vec![(0, 0)])
+            .with_path(vec![(1, 1)])
+            .with_path(vec![(2, 2)]);
+        assert!(matches!(touch.perform(&three), Err(AGError::Custom(_))));
+        touch.wait(Duration::from_millis(5)).unwrap();
+        touch.reset().unwrap();
+        drop(touch);
+        assert_eq!(
+            agent.join().unwrap(),
+            "d 0 400 200 50\nc\nu 0\nc\n\
+             d 0 1200 2000 50\nd 1 2800 2000 50\nc\nw 100\nm 0 1600 2000 50\nm 1 2400 2000 50\nc\nu 0\nu 1\nc\n\
+             w 5\nr\n"
+        );
+    }
+
+    #[test]
+    fn controller_touches_go_through_minitouch() {
+        let server = MockAdbServer::start()
+            .with_features(&["shell_v2"])
+            .with_reply(ROTATION_COMMAND, "    SurfaceOrientation: 0\n")
+            .with_reply("wm size", "Physical size: 1000x2000\n");
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).with_target("emulator-5554").build().unwrap();
+        let (addr, agent) = recording_agent("v 1\n^ 2 1000 2000 0\n$ 99\n");
+        adb.minitouch = Some(Minitouch::new(TcpStream::connect(addr).unwrap(), DISPLAY).unwrap());
+        adb.swipe_with_duration(0, 0, 100, 200, Duration::from_millis(32)).unwrap();
+        adb.long_press(5, 5, Duration::from_millis(16)).unwrap();
+        adb.drag(10, 10, 10, 30, Duration::from_millis(500), Duration::from_millis(16)).unwrap();
+        adb.swipe(0, 0, 0, 10).unwrap();
+        adb.disable_minitouch();
+        let received = agent.join().unwrap();
+        assert!(received.starts_with(
+            "d 0 0 0 0\nc\nw 16\nm 0 50 100 0\nc\nw 16\nm 0 100 200 0\nc\nu 0\nc\n\
+             d 0 5 5 0\nc\nw 16\nm 0 5 5 0\nc\nu 0\nc\n\
+             d 0 10 10 0\nc\nw 500\nw 16\nm 0 10 30 0\nc\nu 0\nc\n"
+        ));
+        assert_eq!(received.matches("d 0").count(), 4);
+        assert!(!server.requests().iter().any(|r| r.starts_with("shell,v2,raw:input")));
+    }
+
+    #[test]
+    fn controller_touches_follow_rotation() {
+        let server = MockAdbServer::start()
+            .with_features(&["shell_v2"])
+            .with_reply(ROTATION_COMMAND, "    SurfaceOrientation: 0\n")
+            .with_reply("wm size", "Physical size: 1000x2000\n");
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).with_target("emulator-5554").build().unwrap();
+        let (addr, agent) = recording_agent("v 1\n^ 2 1000 2000 0\n$ 99\n");
+        adb.minitouch = Some(Minitouch::new(TcpStream::connect(addr).unwrap(), DISPLAY).unwrap());
+        adb.click(100, 200).unwrap();
+        server.set_reply(ROTATION_COMMAND, "    SurfaceOrientation: 1\n");
+        adb.click(100, 200).unwrap();
+        adb.disable_minitouch();
+        assert_eq!(agent.join().unwrap(), "d 0 100 200 0\nc\nu 0\nc\nd 0 800 100 0\nc\nu 0\nc\n");
+    }
+
+    #[test]
+    fn gestures_fall_back_to_input() {
+        let server = MockAdbServer::start().with_features(&["shell_v2"]);
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).with_target("emulator-5554").build().unwrap();
+        let swipe = Gesture::new(Duration::from_millis(200)).with_path(vec![(10, 10), (20, 20), (30, 10)]);
+        adb.gesture(&swipe).unwrap();
+        assert!(server.requests().contains(
+            &"shell,v2,raw:input motionevent DOWN 10 10; sleep 0.100; input motionevent MOVE 20 20; sleep 0.100; input motionevent MOVE 30 10; input motionevent UP 30 10".to_string()
+        ));
+        let pinch = Gesture::pinch((500, 500), 300, 100, Duration::from_millis(200));
+        assert!(matches!(adb.gesture(&pinch), Err(AGError::Custom(message)) if message.contains("minitouch")));
+    }
+}
@@ -0,0 +1,601 @@
+//! In-process stand-in for the host ADB server, speaking the smart-socket protocol on a loopback port.
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use image::RgbaImage;
+
+use crate::error::AGError;
+
+use super::codec::{self, Response};
+use super::shell::{legacy_command, EXIT_MARKER};
+
+#[derive(Debug, Clone)]
+pub(crate) struct MockFile {
+    pub data: Vec<u8>,
+    pub mode: u32,
+    pub mtime: u32,
+}
+
+#[derive(Debug, Clone)]
+struct MockDevice {
+    serial: String,
+    state: String,
+    model: String,
+}
+
+#[derive(Debug, Clone, Default)]
+struct MockReply {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    exit_code: u8,
+    endless: bool,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    devices: Vec<MockDevice>,
+    features: Vec<String>,
+    replies: HashMap<String, MockReply>,
+    files: HashMap<String, MockFile>,
+    framebuffer: Option<(Vec<u8>, Vec<u8>)>,
+    /// `(serial, listening side, connecting side)` for both directions.
+    forwards: Vec<(String, String, String)>,
+    reverses: Vec<(String, String, String)>,
+    next_port: u16,
+    /// APKs streamed with `cmd package install -S`.
+    installed: Vec<Vec<u8>>,
+    requests: Vec<String>,
+}
+
+pub(crate) struct MockAdbServer {
+    addr: String,
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockAdbServer {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock adb server");
+        let addr = listener.local_addr().expect("mock adb server addr").to_string();
+        let state = Arc::new(Mutex::new(MockState::default()));
+        let shared = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    break;
+                };
+                let state = shared.clone();
+                thread::spawn(move || {
+                    let _ = handle_client(stream, &state);
+                });
+            }
+        });
+        Self { addr, state }
+    }
+
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    /// Registers a device as already attached, so `host:transport:` succeeds without a prior `host:connect:`.
+    pub fn with_device(self, serial: &str) -> Self {
+        self.with_device_info(serial, "device", "mock")
+    }
+
+    /// Registers a device with the given `host:devices-l` state and model.
+    pub fn with_device_info(self, serial: &str, state: &str, model: &str) -> Self {
+        let device = MockDevice {
+            serial: serial.to_string(),
+            state: state.to_string(),
+            model: model.to_string(),
+        };
+        self.state.lock().unwrap().devices.push(device);
+        self
+    }
+
+    /// Sets the output returned for a `shell:` or `exec:` command.
+    pub fn with_reply(self, cmd: &str, reply: impl Into<Vec<u8>>) -> Self {
+        self.with_shell_reply(cmd, reply, Vec::new(), 0)
+    }
+
+    /// Replaces the output of a command while the server is running.
+    pub fn set_reply(&self, cmd: &str, reply: impl Into<Vec<u8>>) {
+        let reply = MockReply {
+            stdout: reply.into(),
+            ..Default::default()
+        };
+        self.state.lock().unwrap().replies.insert(cmd.to_string(), reply);
+    }
+
+    /// Sets separate stdout, stderr and exit code for a command; the legacy `shell:` service interleaves them.
+    pub fn with_shell_reply(self, cmd: &str, stdout: impl Into<Vec<u8>>, stderr: impl Into<Vec<u8>>, exit_code: u8) -> Self {
+        let reply = MockReply {
+            stdout: stdout.into(),
+            stderr: stderr.into(),
+            exit_code,
+            endless: false,
+        };
+        self.state.lock().unwrap().replies.insert(cmd.to_string(), reply);
+        self
+    }
+
+    /// Makes a command repeat `line` until the client hangs up, like `logcat`.
+    pub fn with_endless_reply(self, cmd: &str, line: &str) -> Self {
+        let reply = MockReply {
+            stdout: line.as_bytes().to_vec(),
+            endless: true,
+            ..Default::default()
+        };
+        self.state.lock().unwrap().replies.insert(cmd.to_string(), reply);
+        self
+    }
+
+    /// Sets the feature list reported by `host-serial:<serial>:features`.
+    pub fn with_features(self, features: &[&str]) -> Self {
+        self.state.lock().unwrap().features = features.iter().map(|f| f.to_string()).collect();
+        self
+    }
+
+    /// Places a regular file on the mock device's filesystem.
+    pub fn with_file(self, path: &str, data: impl Into<Vec<u8>>) -> Self {
+        let file = MockFile {
+            data: data.into(),
+            mode: 0o100644,
+            mtime: 1_700_000_000,
+        };
+        self.state.lock().unwrap().files.insert(path.to_string(), file);
+        self
+    }
+
+    /// The file stored at `path`, e.g. after a push.
+    pub fn file(&self, path: &str) -> Option<MockFile> {
+        self.state.lock().unwrap().files.get(path).cloned()
+    }
+
+    /// Serves `img` as the PNG output of `screencap -p`.
+    pub fn with_screenshot(self, img: &RgbaImage) -> Self {
+        let mut png = std::io::Cursor::new(Vec::new());
+        img.write_to(&mut png, image::ImageOutputFormat::Png).expect("encode png");
+        self.with_reply("screencap -p", png.into_inner())
+    }
+
+    /// Changes the state of a registered device, adding it if missing; `None` removes it.
+    pub fn set_device_state(&self, serial: &str, state: Option<&str>) {
+        let mut guard = self.state.lock().unwrap();
+        guard.devices.retain(|d| d.serial != serial || state.is_some());
+        match (guard.devices.iter_mut().find(|d| d.serial == serial), state) {
+            (Some(device), Some(state)) => device.state = state.to_string(),
+            (None, Some(state)) => guard.devices.push(MockDevice {
+                serial: serial.to_string(),
+                state: state.to_string(),
+                model: "mock".to_string(),
+            }),
+            _ => {}
+        }
+    }
+
+    /// Serves `img` as the output of plain `screencap` (Android 9+ header) and of `screencap | gzip -1`.
+    pub fn with_raw_screenshot(self, img: &RgbaImage) -> Self {
+        let mut raw = Vec::new();
+        for field in [img.width(), img.height(), 1, 0] {
+            raw.extend_from_slice(&field.to_le_bytes());
+        }
+        raw.extend_from_slice(img.as_raw());
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        gzip.write_all(&raw).expect("gzip screenshot");
+        self.with_reply("screencap", raw)
+            .with_reply("screencap | gzip -1", gzip.finish().expect("gzip screenshot"))
+    }
+
+    /// Serves `img` on the `framebuffer:` service with an RGBA8888 header of the given version (1 or 2).
+    pub fn with_framebuffer(self, img: &RgbaImage, version: u32) -> Self {
+        let mut fields = vec![version, 32];
+        if version == 2 {
+            fields.push(0);
+        }
+        fields.extend_from_slice(&[img.as_raw().len() as u32, img.width(), img.height(), 0, 8, 16, 8, 8, 8, 24, 8]);
+        let header = fields.iter().flat_map(|f| f.to_le_bytes()).collect();
+        self.state.lock().unwrap().framebuffer = Some((header, img.as_raw().clone()));
+        self
+    }
+
+    /// Accepts `framebuffer:` and closes the stream without sending a header, as adbd does when it cannot capture.
+    pub fn with_closed_framebuffer(self) -> Self {
+        self.state.lock().unwrap().framebuffer = Some((Vec::new(), Vec::new()));
+        self
+    }
+
+    pub fn installed(&self) -> Vec<Vec<u8>> {
+        self.state.lock().unwrap().installed.clone()
+    }
+
+    /// Every service string received so far, in arrival order.
+    pub fn requests(&self) -> Vec<String> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+fn invalid(e: AGError) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())
+}
+
+fn write_response(stream: &mut TcpStream, response: Response) -> std::io::Result<()> {
+    stream.write_all(&codec::encode_response(&response).map_err(invalid)?)
+}
+
+fn write_okay(stream: &mut TcpStream, payload: Option<&[u8]>) -> std::io::Result<()> {
+    write_response(stream, Response::Okay(payload.map(|p| p.to_vec())))
+}
+
+fn write_fail(stream: &mut TcpStream, message: &str) -> std::io::Result<()> {
+    write_response(stream, Response::Fail(message.as_bytes().to_vec()))
+}
+
+fn read_request(stream: &mut TcpStream) -> std::io::Result<Option<String>> {
+    match codec::read_message(stream) {
+        Ok(payload) => Ok(Some(String::from_utf8_lossy(&payload).to_string())),
+        Err(AGError::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(AGError::Io(e)) => Err(e),
+        Err(e) => Err(invalid(e)),
+    }
+}
+
+enum ForwardReply {
+    /// Second `OKAY`, with the allocated port when `tcp:0` was requested.
+    Done(Option<String>),
+    Failed(String),
+    List(String),
+}
+
+/// `host-serial:<serial>:forward`-family requests, and `reverse:` ones on a transport.
+fn forward_reply(state: &mut MockState, transport: Option<&str>, request: &str) -> Option<ForwardReply> {
+    let (serial, command, reverse) = match (transport, request.strip_prefix("reverse:")) {
+        (Some(serial), Some(command)) => (serial.to_string(), command, true),
+        _ => {
+            let rest = request.strip_prefix("host-serial:")?;
+            let (serial, command) = rest.rsplit_once(":forward:").map(|(s, c)| (s, ("forward", c))).or_else(|| {
+                ["list-forward", "killforward-all"]
+                    .iter()
+                    .find_map(|c| rest.strip_suffix(&format!(":{}", c)).map(|s| (s, (*c, ""))))
+                    .or_else(|| rest.rsplit_once(":killforward:").map(|(s, c)| (s, ("killforward", c))))
+            })?;
+            let (kind, arg) = command;
+            return forward_command(state, serial.to_string(), kind, arg, false);
+        }
+    };
+    let (kind, arg) = command.split_once(':').unwrap_or((command, ""));
+    forward_command(state, serial, kind, arg, reverse)
+}
+
+fn forward_command(state: &mut MockState, serial: String, kind: &str, arg: &str, reverse: bool) -> Option<ForwardReply> {
+    let next_port = 40000 + state.next_port;
+    let list = if reverse { &mut state.reverses } else { &mut state.forwards };
+    let reply = match kind {
+        "forward" => {
+            let arg = arg.strip_prefix("norebind:").unwrap_or(arg);
+            let (from, to) = arg.split_once(';')?;
+            let (mut from, port) = (from.to_string(), from == "tcp:0");
+            if port {
+                from = format!("tcp:{}", next_port);
+            }
+            list.retain(|(s, f, _)| !(s == &serial && f == &from));
+            list.push((serial, from, to.to_string()));
+            ForwardReply::Done(port.then(|| next_port.to_string()))
+        }
+        "list-forward" => ForwardReply::List(
+            list.iter()
+                .filter(|(s, _, _)| !reverse || s == &serial)
+                .map(|(s, from, to)| format!("{} {} {}\n", s, from, to))
+                .collect(),
+        ),
+        "killforward" if list.iter().any(|(s, f, _)| s == &serial && f == arg) => {
+            list.retain(|(s, f, _)| !(s == &serial && f == arg));
+            ForwardReply::Done(None)
+        }
+        "killforward" => ForwardReply::Failed(format!("listener '{}' not found", arg)),
+        "killforward-all" => {
+            list.retain(|(s, _, _)| s != &serial);
+            ForwardReply::Done(None)
+        }
+        _ => return None,
+    };
+    state.next_port += 1;
+    Some(reply)
+}
+
+fn handle_client(mut stream: TcpStream, state: &Mutex<MockState>) -> std::io::Result<()> {
+    let mut transport: Option<String> = None;
+    while let Some(request) = read_request(&mut stream)? {
+        state.lock().unwrap().requests.push(request.clone());
+        let forward = forward_reply(&mut state.lock().unwrap(), transport.as_deref(), &request);
+        if let Some(reply) = forward {
+            write_okay(&mut stream, None)?;
+            return match reply {
+                ForwardReply::Done(port) => write_okay(&mut stream, port.as_deref().map(str::as_bytes)),
+                ForwardReply::Failed(message) => write_fail(&mut stream, &message),
+                ForwardReply::List(list) => stream.write_all(&codec::encode_message(list.as_bytes()).map_err(invalid)?),
+            };
+        }
+        if let Some(serial) = request.strip_prefix("host:transport:") {
+            let device_state = state.lock().unwrap().devices.iter().find(|d| d.serial == serial).map(|d| d.state.clone());
+            match device_state.as_deref() {
+                Some("device") => {
+                    transport = Some(serial.to_string());
+                    write_okay(&mut stream, None)?;
+                    continue;
+                }
+                Some("offline") => return write_fail(&mut stream, "device offline"),
+                Some("unauthorized") => return write_fail(&mut stream, "device unauthorized.\nPlease check the confirmation dialog on your device."),
+                _ => return write_fail(&mut stream, &format!("device '{}' not found", serial)),
+            }
+        } else if let Some(target) = request.strip_prefix("host:connect:") {
+            let mut state = state.lock().unwrap();
+            if !state.devices.iter().any(|d| d.serial == target) {
+                state.devices.push(MockDevice {
+                    serial: target.to_string(),
+                    state: "device".to_string(),
+                    model: "mock".to_string(),
+                });
+            }
+            return write_okay(&mut stream, Some(format!("connected to {}", target).as_bytes()));
+        } else if let Some(serial) = request.strip_prefix("host-serial:").and_then(|r| r.strip_suffix(":features")) {
+            let state = state.lock().unwrap();
+            if !state.devices.iter().any(|d| d.serial == serial) {
+                return write_fail(&mut stream, &format!("device '{}' not found", serial));
+            }
+            return write_okay(&mut stream, Some(state.features.join(",").as_bytes()));
+        } else if request == "host:devices-l" {
+            let state = state.lock().unwrap();
+            let list: String = state
+                .devices
+                .iter()
+                .enumerate()
+                .map(|(i, d)| {
+                    format!(
+                        "{:<22} {} product:{} model:{} device:{} transport_id:{}\n",
+                        d.serial,
+                        d.state,
+                        d.model,
+                        d.model,
+                        d.model,
+                        i + 1
+                    )
+                })
+                .collect();
+            return write_okay(&mut stream, Some(list.as_bytes()));
+        } else if request == "host:track-devices" {
+            write_okay(&mut stream, None)?;
+            let mut last = None;
+            loop {
+                let snapshot: String = state
+                    .lock()
+                    .unwrap()
+                    .devices
+                    .iter()
+                    .map(|d| format!("{}\t{}\n", d.serial, d.state))
+                    .collect();
+                if last.as_ref() != Some(&snapshot) {
+                    stream.write_all(&codec::encode_message(snapshot.as_bytes()).map_err(invalid)?)?;
+                    last = Some(snapshot);
+                }
+                thread::sleep(std::time::Duration::from_millis(10));
+            }
+        } else if request == "framebuffer:" {
+            let framebuffer = state.lock().unwrap().framebuffer.clone();
+            let (Some(_), Some((header, pixels))) = (&transport, framebuffer) else {
+                return write_fail(&mut stream, "closed");
+            };
+            write_okay(&mut stream, None)?;
+            if header.is_empty() {
+                return Ok(());
+            }
+            stream.write_all(&header)?;
+            let mut nudge = [0u8; 1];
+            stream.read_exact(&mut nudge)?;
+            return stream.write_all(&pixels);
+        } else if request == "sync:" {
+            if transport.is_none() {
+                return write_fail(&mut stream, "no devices/emulators found");
+            }
+            write_okay(&mut stream, None)?;
+            return handle_sync(stream, state);
+        } else if let Some(cmd) = request.strip_prefix("shell,v2,raw:") {
+            if transport.is_none() {
+                return write_fail(&mut stream, "no devices/emulators found");
+            }
+            let reply = state.lock().unwrap().replies.get(cmd).cloned().unwrap_or_default();
+            write_okay(&mut stream, None)?;
+            let mut close_stdin = [0u8; 5];
+            stream.read_exact(&mut close_stdin)?;
+            for (id, data) in [(1u8, &reply.stdout), (2, &reply.stderr)] {
+                if !data.is_empty() {
+                    stream.write_all(&[id])?;
+                    stream.write_all(&(data.len() as u32).to_le_bytes())?;
+                    stream.write_all(data)?;
+                }
+            }
+            return stream.write_all(&[3, 1, 0, 0, 0, reply.exit_code]);
+        } else if let Some(cmd) = request.strip_prefix("shell:") {
+            if transport.is_none() {
+                return write_fail(&mut stream, "no devices/emulators found");
+            }
+            let legacy = cmd.strip_suffix(legacy_command("").as_str());
+            let reply = state.lock().unwrap().replies.get(legacy.unwrap_or(cmd)).cloned().unwrap_or_default();
+            write_okay(&mut stream, None)?;
+            if reply.endless {
+                loop {
+                    stream.write_all(&reply.stdout)?;
+                    thread::sleep(std::time::Duration::from_millis(5));
+                }
+            }
+            stream.write_all(&reply.stdout)?;
+            stream.write_all(&reply.stderr)?;
+            if legacy.is_some() {
+                stream.write_all(format!("{}{}", EXIT_MARKER, reply.exit_code).as_bytes())?;
+            }
+            return Ok(());
+        } else if let Some(cmd) = request.strip_prefix("exec:") {
+            if transport.is_none() {
+                return write_fail(&mut stream, "no devices/emulators found");
+            }
+            if let Some(size) = cmd.strip_prefix("cmd package install -S ") {
+                let size = size.split_whitespace().next().and_then(|s| s.parse().ok()).unwrap_or(0);
+                write_okay(&mut stream, None)?;
+                let mut apk = vec![0u8; size];
+                stream.read_exact(&mut apk)?;
+                let mut state = state.lock().unwrap();
+                state.installed.push(apk);
+                let reply = state.replies.get("cmd package install").map(|r| r.stdout.clone());
+                return stream.write_all(&reply.unwrap_or(b"Success\n".to_vec()));
+            }
+            let reply = state.lock().unwrap().replies.get(cmd).cloned().unwrap_or_default();
+            write_okay(&mut stream, None)?;
+            return stream.write_all(&reply.stdout);
+        } else {
+            return write_fail(&mut stream, "unknown host service");
+        }
+    }
+    Ok(())
+}
+
+fn read_sync_request(stream: &mut TcpStream) -> std::io::Result<([u8; 4], Vec<u8>)> {
+    let mut id = [0u8; 4];
+    let mut length = [0u8; 4];
+    stream.read_exact(&mut id)?;
+    stream.read_exact(&mut length)?;
+    let mut payload = vec![0u8; u32::from_le_bytes(length) as usize];
+    stream.read_exact(&mut payload)?;
+    Ok((id, payload))
+}
+
+fn write_sync(stream: &mut TcpStream, id: &[u8; 4], fields: &[&[u8]]) -> std::io::Result<()> {
+    stream.write_all(id)?;
+    for field in fields {
+        stream.write_all(field)?;
+    }
+    Ok(())
+}
+
+fn write_sync_fail(stream: &mut TcpStream, message: &str) -> std::io::Result<()> {
+    write_sync(stream, b"FAIL", &[&(message.len() as u32).to_le_bytes(), message.as_bytes()])
+}
+
+/// Stat fields for `path`: a stored file, a directory implied by a stored file beneath it, or `None`.
+fn lookup(files: &HashMap<String, MockFile>, path: &str) -> Option<(u32, u64, u32)> {
+    if let Some(file) = files.get(path) {
+        return Some((file.mode, file.data.len() as u64, file.mtime));
+    }
+    let prefix = format!("{}/", path.trim_end_matches('/'));
+    files.keys().any(|k| k.starts_with(&prefix)).then_some((0o040755, 0, 0))
+}
+
+fn stat_v2(error: u32, mode: u32, size: u64, mtime: u32) -> Vec<u8> {
+    let mut out = Vec::with_capacity(68);
+    out.extend_from_slice(&error.to_le_bytes());
+    out.extend_from_slice(&[0u8; 16]);
+    out.extend_from_slice(&mode.to_le_bytes());
+    out.extend_from_slice(&[0u8; 12]);
+    out.extend_from_slice(&size.to_le_bytes());
+    out.extend_from_slice(&0u64.to_le_bytes());
+    out.extend_from_slice(&(mtime as u64).to_le_bytes());
+    out.extend_from_slice(&0u64.to_le_bytes());
+    out
+}
+
+fn handle_sync(mut stream: TcpStream, state: &Mutex<MockState>) -> std::io::Result<()> {
+    loop {
+        let (id, payload) = read_sync_request(&mut stream)?;
+        let path = String::from_utf8_lossy(&payload).to_string();
+        match &id {
+            b"SEND" => {
+                let (path, mode) = path.rsplit_once(',').unwrap_or((&path, "420"));
+                let mode = mode.parse::<u32>().unwrap_or(0o644);
+                let mut data = Vec::new();
+                loop {
+                    let mut id = [0u8; 4];
+                    let mut value = [0u8; 4];
+                    stream.read_exact(&mut id)?;
+                    stream.read_exact(&mut value)?;
+                    let value = u32::from_le_bytes(value);
+                    if &id == b"DONE" {
+                        let file = MockFile {
+                            data,
+                            mode: 0o100000 | (mode & 0o7777),
+                            mtime: value,
+                        };
+                        state.lock().unwrap().files.insert(path.to_string(), file);
+                        break;
+                    }
+                    let mut chunk = vec![0u8; value as usize];
+                    stream.read_exact(&mut chunk)?;
+                    data.extend_from_slice(&chunk);
+                }
+                write_sync(&mut stream, b"OKAY", &[&0u32.to_le_bytes()])?;
+            }
+            b"RECV" => {
+                let Some(file) = state.lock().unwrap().files.get(&path).cloned() else {
+                    write_sync_fail(&mut stream, "No such file or directory")?;
+                    continue;
+                };
+                for chunk in file.data.chunks(64 * 1024) {
+                    write_sync(&mut stream, b"DATA", &[&(chunk.len() as u32).to_le_bytes(), chunk])?;
+                }
+                write_sync(&mut stream, b"DONE", &[&0u32.to_le_bytes()])?;
+            }
+            b"STAT" => {
+                let (mode, size, mtime) = lookup(&state.lock().unwrap().files, &path).unwrap_or((0, 0, 0));
+                write_sync(
+                    &mut stream,
+                    b"STAT",
+                    &[&mode.to_le_bytes(), &(size as u32).to_le_bytes(), &mtime.to_le_bytes()],
+                )?;
+            }
+            b"STA2" => {
+                let body = match lookup(&state.lock().unwrap().files, &path) {
+                    Some((mode, size, mtime)) => stat_v2(0, mode, size, mtime),
+                    None => stat_v2(2, 0, 0, 0),
+                };
+                write_sync(&mut stream, b"STA2", &[&body])?;
+            }
+            b"LIST" | b"LIS2" => {
+                let prefix = format!("{}/", path.trim_end_matches('/'));
+                let files = state.lock().unwrap().files.clone();
+                let mut names: Vec<&str> = files
+                    .keys()
+                    .filter_map(|k| k.strip_prefix(&prefix))
+                    .map(|rest| rest.split('/').next().unwrap_or(rest))
+                    .collect();
+                names.sort();
+                names.dedup();
+                for name in [".", ".."].into_iter().chain(names) {
+                    let (mode, size, mtime) = match name {
+                        "." | ".." => (0o040755, 0, 0),
+                        _ => lookup(&files, &format!("{}{}", prefix, name)).unwrap_or((0, 0, 0)),
+                    };
+                    let name_len = (name.len() as u32).to_le_bytes();
+                    if &id == b"LIST" {
+                        let fields: [&[u8]; 5] = [
+                            &mode.to_le_bytes(),
+                            &(size as u32).to_le_bytes(),
+                            &mtime.to_le_bytes(),
+                            &name_len,
+                            name.as_bytes(),
+                        ];
+                        write_sync(&mut stream, b"DENT", &fields)?;
+                    } else {
+                        write_sync(&mut stream, b"DNT2", &[&stat_v2(0, mode, size, mtime), &name_len, name.as_bytes()])?;
+                    }
+                }
+                if &id == b"LIST" {
+                    write_sync(&mut stream, b"DONE", &[&[0u8; 16]])?;
+                } else {
+                    write_sync(&mut stream, b"DONE", &[&[0u8; 72]])?;
+                }
+            }
+            b"QUIT" => return Ok(()),
+            _ => return write_sync_fail(&mut stream, "unknown sync request"),
+        }
+    }
+}
@@ -0,0 +1,586 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+
+use crate::error::AGResult;
+
+use super::{AGError, Controller, Gesture};
+
+mod app;
+pub mod codec;
+mod devices;
+mod direct;
+mod discover;
+mod display;
+mod forward;
+mod framebuffer;
+mod hierarchy;
+mod input;
+mod logcat;
+mod minicap;
+mod minitouch;
+#[cfg(test)]
+pub(crate) mod mock;
+mod package;
+mod props;
+mod reconnect;
+mod scrcpy;
+mod screencap;
+mod shell;
+mod sync;
+mod track;
+pub use app::Activity;
+pub use devices::{DeviceInfo, DeviceSelector, DeviceState};
+pub use direct::{AdbKey, Connection};
+pub use discover::{Candidate, Discovery, PortRange};
+pub use display::DisplayInfo;
+pub use forward::{Forward, ForwardSpec};
+pub use framebuffer::{Channel, FramebufferHeader};
+pub use hierarchy::{Selector, UiHierarchy, UiNode};
+pub use logcat::{LogEntry, LogLevel, Logcat, LogcatFilter};
+pub use minicap::{Minicap, MinicapBanner};
+pub use minitouch::{Minitouch, MinitouchBanner};
+pub use package::{InstallOptions, PackageVersion};
+pub use props::{parse_getprop, DeviceProfile, Emulator};
+pub use reconnect::ReconnectPolicy;
+pub use scrcpy::{ControlMessage, CopyKey, DeviceMessage, KeyAction, Position, ScrcpyControl, ScreenPowerMode, TouchAction};
+pub use screencap::{PixelFormat, RawHeader, ScreenshotMode};
+pub use shell::{ShellOutput, ShellStream};
+pub use sync::{DirEntry, FileStat};
+pub use track::{DeviceEvent, DeviceTracker};
+
+use direct::DirectBridge;
+
+pub struct RecvData {
+    pub is_ok: bool,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AdbBuilder {
+    addr: Option<String>,
+    timeout: Option<std::time::Duration>,
+    bin_path: Option<String>,
+    target: Option<String>,
+    selector: Option<DeviceSelector>,
+    screenshot_mode: ScreenshotMode,
+    connection: Connection,
+    key_path: Option<PathBuf>,
+    reconnect: Option<ReconnectPolicy>,
+}
+
+impl AdbBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_addr(mut self, addr: &str) -> Self {
+        self.addr = Some(addr.to_string());
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_bin_path(mut self, bin_path: &str) -> Self {
+        self.bin_path = Some(bin_path.to_string());
+        self
+    }
+
+    pub fn with_target(mut self, target: &str) -> Self {
+        self.target = Some(target.to_string());
+        self
+    }
+
+    /// Picks an already attached device by serial instead of `host:connect`ing to a target.
+    pub fn with_serial(self, serial: &str) -> Self {
+        self.with_selector(DeviceSelector::Serial(serial.to_string()))
+    }
+
+    pub fn with_model(self, model: &str) -> Self {
+        self.with_selector(DeviceSelector::Model(model.to_string()))
+    }
+
+    pub fn with_only_device(self) -> Self {
+        self.with_selector(DeviceSelector::Only)
+    }
+
+    pub fn with_selector(mut self, selector: DeviceSelector) -> Self {
+        self.selector = Some(selector);
+        self
+    }
+
+    pub fn with_screenshot_mode(mut self, mode: ScreenshotMode) -> Self {
+        self.screenshot_mode = mode;
+        self
+    }
+
+    /// With `Connection::Direct` the target is the adbd address itself and no ADB server is needed. Host-side forwards
+    /// are not available there, so `forward` and the minitouch, minicap and scrcpy agents fail with `AGError::AdbFail`.
+    pub fn with_connection(mut self, connection: Connection) -> Self {
+        self.connection = connection;
+        self
+    }
+
+    /// Key used to authenticate direct connections; defaults to `~/.android/adbkey`, created if missing.
+    pub fn with_key_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.key_path = Some(path.into());
+        self
+    }
+
+    /// Reconnects when the server or device drops, retrying calls that are safe to repeat. Off by default.
+    pub fn with_reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = Some(policy);
+        self
+    }
+
+    pub fn build(self) -> Result<ADB, AGError> {
+        let addr = self.addr.unwrap_or("127.0.0.1:5037".to_string());
+        let timeout = self.timeout.unwrap_or(std::time::Duration::from_secs(3));
+        #[cfg(target_os = "windows")]
+        let bin_path = self.bin_path.unwrap_or("adb.exe".to_string());
+        #[cfg(not(target_os = "windows"))]
+        let bin_path = self.bin_path.unwrap_or("adb".to_string());
+        let target = self.target.clone().unwrap_or("127.0.0.1:5555".to_string());
+        let (stream, bridge) = match self.connection {
+            Connection::Server => match TcpStream::connect(&addr) {
+                Ok(stream) => (stream, None),
+                Err(_) => {
+                    ADB::start_daemon(&bin_path)?;
+                    (TcpStream::connect(&addr)?, None)
+                }
+            },
+            Connection::Direct => {
+                let key_path = self
+                    .key_path
+                    .or_else(AdbKey::default_path)
+                    .ok_or_else(|| AGError::Custom("no home directory for the adb key".to_string()))?;
+                let bridge = DirectBridge::start(&target, &AdbKey::load_or_generate(&key_path)?, timeout)?;
+                (TcpStream::connect(bridge.addr())?, Some(bridge))
+            }
+        };
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        let reconnect = self.reconnect.filter(|_| self.connection == Connection::Server);
+        let mut adb = ADB {
+            stream,
+            target,
+            server_addr: addr,
+            bin_path,
+            connect_target: self.target.is_some(),
+            reconnect,
+            features: None,
+            forwards: Vec::new(),
+            reverses: Vec::new(),
+            minitouch: None,
+            minicap: None,
+            scrcpy: None,
+            lost_sessions: Vec::new(),
+            display: None,
+            profile: None,
+            screenshot_mode: self.screenshot_mode,
+            _bridge: bridge,
+        };
+        if let Some(target) = self.target {
+            adb.connect(&target)?;
+        }
+        if let Some(selector) = &self.selector {
+            let devices = adb.devices()?;
+            adb.target = selector.select(&devices)?.serial.clone();
+        }
+        Ok(adb)
+    }
+}
+
+#[derive(Debug)]
+pub struct ADB {
+    pub stream: TcpStream,
+    pub target: String,
+    /// Where `reconnect` finds the server again, and how it starts one.
+    server_addr: String,
+    bin_path: String,
+    /// Whether `target` was `host:connect`ed, and so has to be again after a reconnect.
+    connect_target: bool,
+    reconnect: Option<ReconnectPolicy>,
+    features: Option<Vec<String>>,
+    display: Option<DisplayInfo>,
+    /// Filled by `device_profile`, cleared by `connect`.
+    profile: Option<DeviceProfile>,
+    pub screenshot_mode: ScreenshotMode,
+    /// Forwards and reverse forwards created through this instance, removed on drop.
+    forwards: Vec<ForwardSpec>,
+    reverses: Vec<ForwardSpec>,
+    /// Set by `enable_minitouch`; takes over every touch from `input`.
+    minitouch: Option<Minitouch>,
+    /// Set by `enable_minicap`; takes over `screenshot` from `screencap`.
+    minicap: Option<Minicap>,
+    /// Set by `enable_scrcpy`; takes over input from `input`.
+    scrcpy: Option<ScrcpyControl>,
+    /// Agents and forwards dropped by reconnects, until `take_lost_sessions`.
+    lost_sessions: Vec<String>,
+    /// Keeps a `Connection::Direct` bridge, which `stream` points at, alive for as long as the `ADB`.
+    _bridge: Option<DirectBridge>,
+}
+
+impl ADB {
+    pub(crate) fn send_data(&mut self, data: &[u8]) -> Result<(), AGError> {
+        self.stream.write_all(&codec::encode_message(data)?)?;
+        Ok(())
+    }
+
+    pub(crate) fn check_okay(&mut self) -> Result<bool, AGError> {
+        Ok(codec::read_status(&mut self.stream)? == codec::Status::Okay)
+    }
+
+    /// Reads a 4-hex-digit length prefix followed by that many bytes.
+    pub(crate) fn recv_hex_data(&mut self) -> Result<Vec<u8>, AGError> {
+        codec::read_message(&mut self.stream)
+    }
+
+    pub(crate) fn reset(&mut self) -> Result<(), AGError> {
+        self.detach()?;
+        Ok(())
+    }
+
+    /// Opens a fresh control stream with the same timeouts and hands back the old one, e.g. to a streaming reader.
+    pub(crate) fn detach(&mut self) -> Result<TcpStream, AGError> {
+        let addr = self.stream.peer_addr()?;
+        let read_timeout = self.stream.read_timeout()?;
+        let write_timeout = self.stream.write_timeout()?;
+        let stream = TcpStream::connect(addr)?;
+        stream.set_read_timeout(read_timeout)?;
+        stream.set_write_timeout(write_timeout)?;
+        Ok(std::mem::replace(&mut self.stream, stream))
+    }
+
+    /// Sends `service` and waits for the verdict. A `FAIL` reply becomes a typed error and resets the stream.
+    pub(crate) fn open_service(&mut self, service: &str) -> Result<(), AGError> {
+        let is_ok = self.send_data(service.as_bytes()).and_then(|_| self.check_okay());
+        match is_ok.map_err(|e| e.in_service(&self.target, service))? {
+            true => Ok(()),
+            false => {
+                let message = self.recv_hex_data().map(|m| String::from_utf8_lossy(&m).to_string()).unwrap_or_default();
+                self.reset()?;
+                Err(AGError::adb_fail(&self.target, service, message))
+            }
+        }
+    }
+
+    /// `open_service` followed by the hex-length-prefixed reply of a one-shot host service.
+    pub(crate) fn host_request(&mut self, service: &str) -> Result<String, AGError> {
+        self.open_service(service)?;
+        let data = self.recv_hex_data().map_err(|e| e.in_service(&self.target, service));
+        self.reset()?;
+        Ok(String::from_utf8_lossy(&data?).to_string())
+    }
+
+    /// Runs `f` with a longer read timeout, for commands like `pm install` that stay silent while they work.
+    pub(crate) fn with_read_timeout<T>(&mut self, timeout: std::time::Duration, f: impl FnOnce(&mut Self) -> AGResult<T>) -> AGResult<T> {
+        let previous = self.stream.read_timeout()?;
+        self.stream.set_read_timeout(Some(timeout))?;
+        let result = f(self);
+        // `reset` copies the timeout to the new control stream, so restore it on whichever one is current.
+        self.stream.set_read_timeout(previous)?;
+        result
+    }
+
+    pub(crate) fn transport(&mut self) -> Result<(), AGError> {
+        self.open_service(&format!("host:transport:{}", self.target))
+    }
+
+    fn run(&mut self, service: &str) -> Result<RecvData, AGError> {
+        let request = codec::encode_message(service.as_bytes())?;
+        self.transport()?;
+        self.stream.write_all(&request)?;
+        let is_ok = self.check_okay().map_err(|e| e.in_service(&self.target, service))?;
+        let mut data = Vec::new();
+        let read = self.stream.read_to_end(&mut data);
+        self.reset()?;
+        read.map_err(|e| AGError::from(e).in_service(&self.target, service))?;
+        Ok(RecvData { is_ok, data })
+    }
+
+    pub fn shell(&mut self, cmd: &str) -> Result<RecvData, AGError> {
+        self.run(&format!("shell:{}", cmd))
+    }
+
+    pub fn exec(&mut self, cmd: &str) -> Result<RecvData, AGError> {
+        self.run(&format!("exec:{}", cmd))
+    }
+
+    /// Runs `host:connect`. The server answers `OKAY` even when it cannot reach the target, so the message is checked too.
+    pub fn connect(&mut self, target: &str) -> Result<(), AGError> {
+        let service = format!("host:connect:{}", target);
+        let message = self.host_request(&service)?;
+        if message.starts_with("failed") || message.starts_with("unable") || message.starts_with("cannot") {
+            return Err(AGError::AdbFail {
+                serial: target.to_string(),
+                service,
+                message,
+            });
+        }
+        self.target = target.to_string();
+        self.features = None;
+        self.display = None;
+        self.profile = None;
+        Ok(())
+    }
+
+    /// Feature list advertised by the device, e.g. `stat_v2`, `ls_v2`, `shell_v2`. Cached until the next `connect`.
+    pub fn features(&mut self) -> AGResult<Vec<String>> {
+        if let Some(features) = &self.features {
+            return Ok(features.clone());
+        }
+        let data = self.host_request(&format!("host-serial:{}:features", self.target))?;
+        let features: Vec<String> = data.split(',').filter(|f| !f.is_empty()).map(|f| f.to_string()).collect();
+        self.features = Some(features.clone());
+        Ok(features)
+    }
+
+    pub(crate) fn has_feature(&mut self, feature: &str) -> AGResult<bool> {
+        Ok(self.features()?.iter().any(|f| f == feature))
+    }
+
+    pub fn start_daemon(bin_path: &str) -> Result<(), AGError> {
+        std::process::Command::new(bin_path).arg("start-server").spawn()?;
+        Ok(())
+    }
+
+    pub fn into_dyn(self) -> Box<dyn Controller> {
+        Box::new(self) as _
+    }
+}
+
+impl Drop for ADB {
+    fn drop(&mut self) {
+        self.remove_forwards();
+    }
+}
+
+impl Controller for ADB {
+    fn screenshot(&mut self) -> AGResult<image::RgbaImage> {
+        self.retry_idempotent(|adb| {
+            if let Some(minicap) = &adb.minicap {
+                return minicap.screenshot();
+            }
+            adb.screencap(adb.screenshot_mode)
+        })
+    }
+
+    fn click(&mut self, x: u32, y: u32) -> AGResult<()> {
+        self.run_once("click", |adb| {
+            if let Some(minitouch) = adb.oriented_minitouch()? {
+                return minitouch.tap(x, y);
+            }
+            if let Some(scrcpy) = adb.scrcpy.as_mut() {
+                return scrcpy.tap(x, y);
+            }
+            adb.shell_checked(&format!("input tap {} {}", x, y))?;
+            Ok(())
+        })
+    }
+
+    fn swipe(&mut self, x1: u32, y1: u32, x2: u32, y2: u32) -> AGResult<()> {
+        self.run_once("swipe", |adb| {
+            if let Some(minitouch) = adb.oriented_minitouch()? {
+                return minitouch.swipe((x1, y1), (x2, y2), input::DEFAULT_SWIPE_DURATION);
+            }
+            if let Some(scrcpy) = adb.scrcpy.as_mut() {
+                return scrcpy.swipe((x1, y1), (x2, y2), input::DEFAULT_SWIPE_DURATION);
+            }
+            adb.shell_checked(&format!("input swipe {} {} {} {}", x1, y1, x2, y2))?;
+            Ok(())
+        })
+    }
+
+    fn swipe_with_duration(&mut self, x1: u32, y1: u32, x2: u32, y2: u32, duration: std::time::Duration) -> AGResult<()> {
+        self.run_once("swipe", |adb| {
+            if let Some(minitouch) = adb.oriented_minitouch()? {
+                return minitouch.swipe((x1, y1), (x2, y2), duration);
+            }
+            if let Some(scrcpy) = adb.scrcpy.as_mut() {
+                return scrcpy.swipe((x1, y1), (x2, y2), duration);
+            }
+            adb.shell_checked(&format!("input swipe {} {} {} {} {}", x1, y1, x2, y2, duration.as_millis()))?;
+            Ok(())
+        })
+    }
+
+    fn drag(&mut self, x1: u32, y1: u32, x2: u32, y2: u32, hold: std::time::Duration, duration: std::time::Duration) -> AGResult<()> {
+        self.run_once("drag", |adb| {
+            if let Some(minitouch) = adb.oriented_minitouch()? {
+                return minitouch.drag((x1, y1), (x2, y2), hold, duration);
+            }
+            if let Some(scrcpy) = adb.scrcpy.as_mut() {
+                return scrcpy.drag((x1, y1), (x2, y2), hold, duration);
+            }
+            adb.shell_checked(&input::drag_command((x1, y1), (x2, y2), hold, duration))?;
+            Ok(())
+        })
+    }
+
+    /// Goes through minitouch when enabled. Otherwise single-finger gestures are played with `input motionevent`.
+    fn gesture(&mut self, gesture: &Gesture) -> AGResult<()> {
+        self.run_once("gesture", |adb| {
+            if let Some(minitouch) = adb.oriented_minitouch()? {
+                return minitouch.perform(gesture);
+            }
+            match gesture.paths().len() {
+                0 => Ok(()),
+                1 => {
+                    adb.shell_checked(&input::gesture_command(gesture))?;
+                    Ok(())
+                }
+                _ => Err(AGError::Custom(
+                    "multi-finger gestures need minitouch, see `ADB::enable_minitouch`".to_string(),
+                )),
+            }
+        })
+    }
+
+    fn press_key(&mut self, keycode: u32) -> AGResult<()> {
+        self.run_once("press_key", |adb| {
+            if let Some(scrcpy) = adb.scrcpy.as_mut() {
+                return scrcpy.key(keycode, 0);
+            }
+            adb.shell_checked(&format!("input keyevent {}", keycode))?;
+            Ok(())
+        })
+    }
+
+    fn get_resolution(&mut self) -> AGResult<(u32, u32)> {
+        self.retry_idempotent(|adb| Ok(adb.display_info()?.effective_size()))
+    }
+
+    /// Plain ASCII goes through `input text`; anything else through `input_unicode`. With scrcpy, plain ASCII is
+    /// injected as text and anything else pasted from the clipboard.
+    fn input_text(&mut self, text: &str) -> AGResult<()> {
+        self.run_once("input_text", |adb| {
+            if let Some(scrcpy) = adb.scrcpy.as_mut() {
+                if input::is_plain_text(text) && text.len() <= ControlMessage::INJECT_TEXT_MAX_LENGTH {
+                    return scrcpy.text(text);
+                }
+                return scrcpy.set_clipboard(text, true);
+            }
+            if !input::is_plain_text(text) {
+                return adb.input_unicode(text);
+            }
+            adb.shell_checked(&input::input_text_command(text))?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::MockAdbServer;
+    use super::*;
+    use std::time::Duration;
+
+    fn build(server: &MockAdbServer) -> ADB {
+        AdbBuilder::new().with_addr(server.addr()).with_target("127.0.0.1:7555").build().unwrap()
+    }
+
+    #[test]
+    fn build_connects_to_target() {
+        let server = MockAdbServer::start();
+        let adb = build(&server);
+        assert_eq!(adb.target, "127.0.0.1:7555");
+        assert_eq!(server.requests(), ["host:connect:127.0.0.1:7555"]);
+    }
+
+    #[test]
+    fn screenshot_decodes_png() {
+        let img = image::RgbaImage::from_pixel(4, 3, image::Rgba([1, 2, 3, 255]));
+        let server = MockAdbServer::start().with_screenshot(&img);
+        let mut adb = build(&server);
+        assert_eq!(adb.screenshot().unwrap(), img);
+        assert!(server
+            .requests()
+            .ends_with(&["host:transport:127.0.0.1:7555".to_string(), "exec:screencap -p".to_string()]));
+    }
+
+    #[test]
+    fn input_commands() {
+        let server = MockAdbServer::start().with_features(&["shell_v2"]);
+        let mut adb = build(&server);
+        adb.click(10, 20).unwrap();
+        adb.swipe(1, 2, 3, 4).unwrap();
+        adb.press_key(4).unwrap();
+        adb.input_text("hello").unwrap();
+        adb.swipe_with_duration(1, 2, 3, 4, Duration::from_millis(800)).unwrap();
+        adb.long_press(5, 6, Duration::from_secs(1)).unwrap();
+        let shells: Vec<_> = server.requests().into_iter().filter(|r| r.starts_with("shell,v2,raw:")).collect();
+        assert_eq!(
+            shells,
+            [
+                "shell,v2,raw:input tap 10 20",
+                "shell,v2,raw:input swipe 1 2 3 4",
+                "shell,v2,raw:input keyevent 4",
+                "shell,v2,raw:input text hello",
+                "shell,v2,raw:input swipe 1 2 3 4 800",
+                "shell,v2,raw:input swipe 5 6 5 6 1000",
+            ]
+        );
+    }
+
+    #[test]
+    fn shell_returns_output() {
+        let server = MockAdbServer::start().with_device("emulator-5554").with_reply("echo hi", "hi\n");
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).build().unwrap();
+        adb.target = "emulator-5554".to_string();
+        let recv = adb.shell("echo hi").unwrap();
+        assert!(recv.is_ok);
+        assert_eq!(recv.data, b"hi\n");
+    }
+
+    #[test]
+    fn transport_to_unknown_device_fails() {
+        let server = MockAdbServer::start();
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).build().unwrap();
+        assert!(matches!(adb.shell("echo hi"), Err(AGError::DeviceNotFound { .. })));
+    }
+
+    #[test]
+    fn fail_replies_are_typed() {
+        let server = MockAdbServer::start()
+            .with_device_info("offline-1", "offline", "a")
+            .with_device_info("locked-1", "unauthorized", "b");
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).build().unwrap();
+        adb.target = "offline-1".to_string();
+        assert!(matches!(adb.shell_v2("true"), Err(AGError::DeviceOffline { serial }) if serial == "offline-1"));
+        adb.target = "locked-1".to_string();
+        assert!(matches!(adb.framebuffer(), Err(AGError::DeviceUnauthorized { .. })));
+        assert!(adb.devices().is_ok());
+    }
+
+    #[test]
+    fn oversized_command_is_rejected() {
+        let server = MockAdbServer::start();
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).with_target("emulator-5554").build().unwrap();
+        let text = "a".repeat(70_000);
+        assert!(matches!(adb.shell(&text), Err(AGError::PayloadTooLarge(_))));
+        assert!(adb.shell("echo hi").unwrap().is_ok);
+    }
+
+    #[test]
+    fn silent_server_times_out() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            let _held = listener.accept();
+            std::thread::sleep(Duration::from_secs(2));
+        });
+        let mut adb = AdbBuilder::new()
+            .with_addr(&addr)
+            .with_timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        match adb.devices() {
+            Err(AGError::Timeout { service, .. }) => assert_eq!(service, "host:devices-l"),
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+    }
+}
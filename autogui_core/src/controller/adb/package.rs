@@ -0,0 +1,278 @@
+//! APK installation and package queries through `cmd package` and `pm`.
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::error::{AGError, AGResult};
+
+use super::ADB;
+
+/// Installs verify and dexopt the APK before answering, which can take minutes on slow emulators.
+const INSTALL_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InstallOptions {
+    /// `-r`: replace an existing installation, keeping its data.
+    pub replace: bool,
+    /// `-d`: allow a lower version code than the installed one.
+    pub allow_downgrade: bool,
+    /// `-g`: grant all runtime permissions.
+    pub grant_permissions: bool,
+    /// `-t`: allow APKs with `android:testOnly`.
+    pub allow_test: bool,
+}
+
+impl InstallOptions {
+    fn flags(&self) -> String {
+        [
+            (self.replace, " -r"),
+            (self.allow_downgrade, " -d"),
+            (self.grant_permissions, " -g"),
+            (self.allow_test, " -t"),
+        ]
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, flag)| *flag)
+        .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageVersion {
+    pub code: u64,
+    pub name: Option<String>,
+}
+
+/// Checks `pm`/`cmd package` output for `Success`, turning `Failure [CODE: message]` into `AGError::Package`.
+pub(crate) fn parse_pm_result(output: &str) -> AGResult<()> {
+    if output.lines().any(|l| l.trim() == "Success") {
+        return Ok(());
+    }
+    let failure = output
+        .lines()
+        .find_map(|l| l.trim().strip_prefix("Failure [").and_then(|f| f.strip_suffix(']')));
+    let (code, message) = match failure {
+        Some(failure) => failure.split_once(':').unwrap_or((failure, "")),
+        None => ("UNKNOWN", output),
+    };
+    Err(AGError::Package {
+        code: code.trim().to_string(),
+        message: message.trim().to_string(),
+    })
+}
+
+/// Version of `package` from `dumpsys package <package>`, taken from its first `Package [name]` block.
+pub(crate) fn parse_version(output: &str, package: &str) -> Option<PackageVersion> {
+    let header = format!("Package [{}]", package);
+    let mut lines = output.lines().skip_while(|l| !l.trim_start().starts_with(&header)).skip(1);
+    let (mut code, mut name) = (None, None);
+    for line in lines.by_ref().take_while(|l| !l.trim_start().starts_with("Package [")) {
+        let line = line.trim();
+        if let Some(rest) = line.strip_prefix("versionCode=") {
+            code = rest.split_whitespace().next().and_then(|c| c.parse().ok());
+        } else if let Some(rest) = line.strip_prefix("versionName=") {
+            name = Some(rest.to_string());
+        }
+    }
+    Some(PackageVersion { code: code?, name })
+}
+
+/// Whether `name` sticks to the characters of Java package names, plus `$` for class names.
+pub(crate) fn is_java_name(name: &str, class: bool) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || (class && c == '$'))
+}
+
+pub(crate) fn check_package(package: &str) -> AGResult<()> {
+    if !is_java_name(package, false) {
+        return Err(AGError::InvalidName {
+            kind: "package".to_string(),
+            name: package.to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn remote_name(apk: &Path) -> String {
+    let name = apk.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || "._-".contains(c) { c } else { '_' })
+        .collect()
+}
+
+impl ADB {
+    /// Installs `apk`, streaming it into `cmd package install -S` when the device supports it, otherwise pushing it to
+    /// `/data/local/tmp` and running `pm install`.
+    pub fn install(&mut self, apk: impl AsRef<Path>, options: InstallOptions) -> AGResult<()> {
+        let apk = apk.as_ref();
+        if self.has_feature("cmd")? {
+            self.install_streamed(apk, options)
+        } else {
+            self.install_pushed(apk, options)
+        }
+    }
+
+    fn install_streamed(&mut self, apk: &Path, options: InstallOptions) -> AGResult<()> {
+        let mut file = File::open(apk)?;
+        let service = format!("exec:cmd package install -S {}{}", file.metadata()?.len(), options.flags());
+        let output = self.with_read_timeout(INSTALL_TIMEOUT, |adb| {
+            adb.transport()?;
+            adb.open_service(&service)?;
+            let mut output = String::new();
+            let result = std::io::copy(&mut file, &mut adb.stream).and_then(|_| adb.stream.read_to_string(&mut output));
+            adb.reset()?;
+            result.map_err(|e| AGError::from(e).in_service(&adb.target, &service))?;
+            Ok(output)
+        })?;
+        parse_pm_result(&output)
+    }
+
+    fn install_pushed(&mut self, apk: &Path, options: InstallOptions) -> AGResult<()> {
+        let remote = format!("/data/local/tmp/{}", remote_name(apk));
+        self.push(apk, &remote, 0o644)?;
+        let cmd = format!("pm install{} {}", options.flags(), remote);
+        let output = self.with_read_timeout(INSTALL_TIMEOUT, |adb| adb.shell_output(&cmd));
+        // Best effort: a leftover file in /data/local/tmp matters less than why the install failed.
+        let _ = self.shell_output(&format!("rm -f {}", remote));
+        let output = output?;
+        parse_pm_result(&format!("{}{}", output.stdout_str(), output.stderr_str()))
+    }
+
+    /// Removes `package`; `keep_data` keeps its data and cache directories like `pm uninstall -k`.
+    pub fn uninstall(&mut self, package: &str, keep_data: bool) -> AGResult<()> {
+        check_package(package)?;
+        let flag = if keep_data { " -k" } else { "" };
+        let output = self.shell_output(&format!("pm uninstall{} {}", flag, package))?;
+        parse_pm_result(&format!("{}{}", output.stdout_str(), output.stderr_str()))
+    }
+
+    /// Names of all installed packages, as listed by `pm list packages`.
+    pub fn list_packages(&mut self) -> AGResult<Vec<String>> {
+        let output = self.shell_checked("pm list packages")?.stdout_str();
+        Ok(output
+            .lines()
+            .filter_map(|l| l.trim().strip_prefix("package:"))
+            .map(|p| p.to_string())
+            .collect())
+    }
+
+    pub fn is_installed(&mut self, package: &str) -> AGResult<bool> {
+        check_package(package)?;
+        let output = self.shell_output(&format!("pm path {}", package))?;
+        Ok(output.success() && output.stdout_str().contains("package:"))
+    }
+
+    /// `None` if `package` is not installed.
+    pub fn package_version(&mut self, package: &str) -> AGResult<Option<PackageVersion>> {
+        check_package(package)?;
+        let output = self.shell_checked(&format!("dumpsys package {}", package))?.stdout_str();
+        Ok(parse_version(&output, package))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock::MockAdbServer;
+    use super::*;
+    use crate::controller::AdbBuilder;
+
+    const DUMPSYS: &str = "Packages:\n  Package [com.example.app] (1f2e3d):\n    userId=10123\n    versionCode=42 minSdk=24 targetSdk=34\n    versionName=1.4.2\n  Package [com.other] (4c5b6a):\n    versionCode=7 minSdk=21 targetSdk=30\n";
+
+    fn temp_apk(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("autogui-{}-{}.apk", name, std::process::id()));
+        std::fs::write(&path, b"PK\x03\x04 not really an apk").unwrap();
+        path
+    }
+
+    #[test]
+    fn parse_outputs() {
+        assert!(parse_pm_result("Performing Streamed Install\nSuccess\n").is_ok());
+        match parse_pm_result("Failure [INSTALL_FAILED_VERSION_DOWNGRADE: Downgrade detected: 5 < 6]\n") {
+            Err(AGError::Package { code, message }) => {
+                assert_eq!(code, "INSTALL_FAILED_VERSION_DOWNGRADE");
+                assert_eq!(message, "Downgrade detected: 5 < 6");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(
+            matches!(parse_pm_result("Failure [DELETE_FAILED_INTERNAL_ERROR]"), Err(AGError::Package { code, .. }) if code == "DELETE_FAILED_INTERNAL_ERROR")
+        );
+        assert!(matches!(parse_pm_result("Error: Unknown option: -x"), Err(AGError::Package { code, .. }) if code == "UNKNOWN"));
+
+        let version = parse_version(DUMPSYS, "com.example.app").unwrap();
+        assert_eq!(
+            version,
+            PackageVersion {
+                code: 42,
+                name: Some("1.4.2".to_string())
+            }
+        );
+        assert_eq!(parse_version(DUMPSYS, "com.other").unwrap().name, None);
+        assert_eq!(parse_version(DUMPSYS, "com.missing"), None);
+        assert_eq!(remote_name(Path::new("/tmp/my app (1).apk")), "my_app__1_.apk");
+    }
+
+    #[test]
+    fn install_streams_apk() {
+        let apk = temp_apk("stream");
+        let server = MockAdbServer::start().with_features(&["cmd", "shell_v2"]);
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).with_target("emulator-5554").build().unwrap();
+        let options = InstallOptions {
+            replace: true,
+            grant_permissions: true,
+            ..Default::default()
+        };
+        adb.install(&apk, options).unwrap();
+        assert_eq!(server.installed(), [std::fs::read(&apk).unwrap()]);
+        assert!(server.requests().iter().any(|r| *r == "exec:cmd package install -S 22 -r -g"));
+        std::fs::remove_file(apk).unwrap();
+    }
+
+    #[test]
+    fn install_falls_back_to_push() {
+        let apk = temp_apk("push");
+        let remote = format!("/data/local/tmp/{}", remote_name(&apk));
+        let server = MockAdbServer::start().with_shell_reply(
+            &format!("pm install -d {}", remote),
+            "Failure [INSTALL_FAILED_OLDER_SDK: Requires newer sdk version #34 (current version is #28)]\n",
+            "",
+            1,
+        );
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).with_target("emulator-5554").build().unwrap();
+        let options = InstallOptions {
+            allow_downgrade: true,
+            ..Default::default()
+        };
+        assert!(matches!(adb.install(&apk, options), Err(AGError::Package { code, .. }) if code == "INSTALL_FAILED_OLDER_SDK"));
+        assert_eq!(server.file(&remote).unwrap().data, std::fs::read(&apk).unwrap());
+        assert!(server.requests().iter().any(|r| r.contains(&format!("rm -f {}", remote))));
+        std::fs::remove_file(apk).unwrap();
+    }
+
+    #[test]
+    fn package_queries() {
+        let server = MockAdbServer::start()
+            .with_features(&["shell_v2"])
+            .with_reply("pm list packages", "package:android\npackage:com.example.app\n")
+            .with_reply("pm path com.example.app", "package:/data/app/base.apk\n")
+            .with_shell_reply("pm path com.missing", "", "", 1)
+            .with_reply("dumpsys package com.example.app", DUMPSYS)
+            .with_reply("pm uninstall -k com.example.app", "Success\n");
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).with_target("emulator-5554").build().unwrap();
+        assert_eq!(adb.list_packages().unwrap(), ["android", "com.example.app"]);
+        assert!(adb.is_installed("com.example.app").unwrap());
+        assert!(!adb.is_installed("com.missing").unwrap());
+        assert_eq!(adb.package_version("com.example.app").unwrap().map(|v| v.code), Some(42));
+        adb.uninstall("com.example.app", true).unwrap();
+        assert!(matches!(adb.uninstall("com.missing", false), Err(AGError::Package { .. })));
+
+        let requests = server.requests().len();
+        assert!(matches!(adb.uninstall("foo; reboot", false), Err(AGError::InvalidName { .. })));
+        assert!(matches!(adb.is_installed("foo$(id)"), Err(AGError::InvalidName { .. })));
+        assert!(matches!(adb.package_version(""), Err(AGError::InvalidName { .. })));
+        assert_eq!(server.requests().len(), requests);
+    }
+}
@@ -0,0 +1,228 @@
+//! System properties from `getprop` and the `DeviceProfile` summarised from them.
+use std::collections::HashMap;
+
+use crate::error::{AGError, AGResult};
+
+use super::ADB;
+
+/// Android emulators told apart by the properties their images set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Emulator {
+    MuMu,
+    LDPlayer,
+    BlueStacks,
+    Nox,
+}
+
+/// Lowercase substrings of property names, or of the identity properties in `IDENTITY_KEYS`, that give a vendor away.
+const MARKERS: &[(Emulator, &[&str])] = &[
+    (Emulator::MuMu, &["nemu", "mumu", "netease"]),
+    (Emulator::LDPlayer, &["ldinit", "ldplayer", "leidian"]),
+    (Emulator::BlueStacks, &["bst.", "bluestacks"]),
+    (Emulator::Nox, &["noxd", "nox."]),
+];
+
+const IDENTITY_KEYS: &[&str] = &[
+    "ro.product.manufacturer",
+    "ro.product.brand",
+    "ro.product.model",
+    "ro.product.name",
+    "ro.build.product",
+];
+
+impl Emulator {
+    /// Best guess from a `getprop` map; `None` for phones and for emulators not listed here.
+    pub fn detect(props: &HashMap<String, String>) -> Option<Self> {
+        let identity: Vec<String> = IDENTITY_KEYS.iter().filter_map(|k| props.get(*k)).map(|v| v.to_lowercase()).collect();
+        MARKERS.iter().find_map(|(emulator, markers)| {
+            let found = markers
+                .iter()
+                .any(|m| props.keys().any(|k| k.to_lowercase().contains(m)) || identity.iter().any(|v| v.contains(m)));
+            found.then_some(*emulator)
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Emulator::MuMu => "MuMu",
+            Emulator::LDPlayer => "LDPlayer",
+            Emulator::BlueStacks => "BlueStacks",
+            Emulator::Nox => "Nox",
+        }
+    }
+}
+
+/// What scripts usually branch on, read once per connection by `ADB::device_profile`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceProfile {
+    /// `ro.build.version.sdk`, e.g. 30 for Android 11.
+    pub sdk: u32,
+    /// `ro.build.version.release`, e.g. `11`.
+    pub release: String,
+    /// Supported ABIs, preferred first.
+    pub abis: Vec<String>,
+    pub manufacturer: String,
+    pub model: String,
+    /// Dots per inch, the `wm density` override if set.
+    pub density: u32,
+    /// Screen size in the natural orientation, the `wm size` override if set.
+    pub screen_size: (u32, u32),
+    pub emulator: Option<Emulator>,
+}
+
+/// Parses `getprop` output, `[name]: [value]` per property. Values may span lines.
+pub fn parse_getprop(output: &str) -> HashMap<String, String> {
+    let mut props = HashMap::new();
+    let mut pending: Option<String> = None;
+    for line in output.lines() {
+        let line = match pending.take() {
+            Some(mut head) => {
+                head.push('\n');
+                head.push_str(line);
+                head
+            }
+            None => line.to_string(),
+        };
+        let Some((name, value)) = line.strip_prefix('[').and_then(|l| l.split_once("]: [")) else {
+            continue;
+        };
+        match value.strip_suffix(']') {
+            Some(value) => {
+                props.insert(name.to_string(), value.to_string());
+            }
+            None => pending = Some(line),
+        }
+    }
+    props
+}
+
+/// Density from `wm density`, preferring `Override density:` over `Physical density:`.
+fn parse_wm_density(output: &str) -> Option<u32> {
+    let find = |key: &str| output.lines().find_map(|l| l.trim().strip_prefix(key)?.trim().parse().ok());
+    find("Override density:").or_else(|| find("Physical density:"))
+}
+
+impl ADB {
+    /// All system properties.
+    pub fn getprop(&mut self) -> AGResult<HashMap<String, String>> {
+        Ok(parse_getprop(&self.shell_checked("getprop")?.stdout_str()))
+    }
+
+    /// Collects the `DeviceProfile`, cached until the next `connect`.
+    pub fn device_profile(&mut self) -> AGResult<DeviceProfile> {
+        if let Some(profile) = &self.profile {
+            return Ok(profile.clone());
+        }
+        let props = self.getprop()?;
+        let prop = |name: &str| props.get(name).cloned().unwrap_or_default();
+        let sdk = prop("ro.build.version.sdk").parse().map_err(|_| AGError::MissingProperty {
+            serial: self.target.clone(),
+            name: "ro.build.version.sdk".to_string(),
+        })?;
+        let abis = match prop("ro.product.cpu.abilist") {
+            list if !list.is_empty() => list.split(',').map(|a| a.to_string()).collect(),
+            _ => vec![prop("ro.product.cpu.abi")],
+        };
+        // Some emulator images ship a `wm` without `density`; the property is good enough there.
+        let wm_density = self.shell_output("wm density")?;
+        let density = wm_density
+            .success()
+            .then(|| parse_wm_density(&wm_density.stdout_str()))
+            .flatten()
+            .or_else(|| prop("ro.sf.lcd_density").parse().ok())
+            .unwrap_or(0);
+        let display = self.display_info()?;
+        let profile = DeviceProfile {
+            sdk,
+            release: prop("ro.build.version.release"),
+            abis,
+            manufacturer: prop("ro.product.manufacturer"),
+            model: prop("ro.product.model"),
+            density,
+            screen_size: display.override_size.unwrap_or(display.physical),
+            emulator: Emulator::detect(&props),
+        };
+        self.profile = Some(profile.clone());
+        Ok(profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::display::ROTATION_COMMAND;
+    use super::super::mock::MockAdbServer;
+    use super::*;
+    use crate::controller::AdbBuilder;
+
+    const GETPROP: &str = "[ro.build.version.release]: [9]\n\
+        [ro.build.version.sdk]: [28]\n\
+        [ro.product.cpu.abilist]: [x86_64,x86,arm64-v8a]\n\
+        [ro.product.manufacturer]: [samsung]\n\
+        [ro.product.model]: [SM-G9750]\n\
+        [ro.sf.lcd_density]: [320]\n\
+        [init.svc.noxd]: [running]\n\
+        [persist.sys.motd]: [first line\n\
+        second line]\n";
+
+    #[test]
+    fn properties_are_parsed() {
+        let props = parse_getprop(GETPROP);
+        assert_eq!(props.len(), 8);
+        assert_eq!(props["ro.product.cpu.abilist"], "x86_64,x86,arm64-v8a");
+        assert_eq!(props["persist.sys.motd"], "first line\nsecond line");
+        assert_eq!(Emulator::detect(&props), Some(Emulator::Nox));
+
+        let phone = parse_getprop("[ro.product.manufacturer]: [Google]\n[ro.product.model]: [Pixel 7]\n");
+        assert_eq!(Emulator::detect(&phone), None);
+        let mumu = parse_getprop("[ro.product.manufacturer]: [Netease]\n[ro.product.model]: [MuMu]\n");
+        assert_eq!(Emulator::detect(&mumu), Some(Emulator::MuMu));
+
+        assert_eq!(parse_wm_density("Physical density: 480\nOverride density: 320\n"), Some(320));
+        assert_eq!(parse_wm_density("Physical density: 480\n"), Some(480));
+    }
+
+    #[test]
+    fn profile_is_cached_per_connection() {
+        let server = MockAdbServer::start()
+            .with_reply("getprop", GETPROP)
+            .with_reply("wm size", "Physical size: 900x1600\n")
+            .with_reply(ROTATION_COMMAND, "    SurfaceOrientation: 1\n");
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).with_target("127.0.0.1:62001").build().unwrap();
+        let profile = adb.device_profile().unwrap();
+        assert_eq!(
+            profile,
+            DeviceProfile {
+                sdk: 28,
+                release: "9".to_string(),
+                abis: vec!["x86_64".to_string(), "x86".to_string(), "arm64-v8a".to_string()],
+                manufacturer: "samsung".to_string(),
+                model: "SM-G9750".to_string(),
+                density: 320,
+                screen_size: (900, 1600),
+                emulator: Some(Emulator::Nox),
+            }
+        );
+        assert_eq!(adb.device_profile().unwrap(), profile);
+        let count = || server.requests().iter().filter(|r| r.contains("getprop")).count();
+        assert_eq!(count(), 1);
+        adb.connect("127.0.0.1:62001").unwrap();
+        adb.device_profile().unwrap();
+        assert_eq!(count(), 2);
+    }
+
+    #[test]
+    fn profile_tolerates_missing_wm_density() {
+        let server = MockAdbServer::start()
+            .with_features(&["shell_v2"])
+            .with_reply("getprop", GETPROP)
+            .with_shell_reply("wm density", "", "Unknown command: density\n", 1)
+            .with_reply("wm size", "Physical size: 900x1600\n")
+            .with_reply(ROTATION_COMMAND, "    SurfaceOrientation: 0\n");
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).with_target("127.0.0.1:62001").build().unwrap();
+        assert_eq!(adb.device_profile().unwrap().density, 320);
+
+        server.set_reply("getprop", "[ro.product.model]: [x]\n");
+        adb.connect("127.0.0.1:62001").unwrap();
+        assert!(matches!(adb.device_profile(), Err(AGError::MissingProperty { name, .. }) if name == "ro.build.version.sdk"));
+    }
+}
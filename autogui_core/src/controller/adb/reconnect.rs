@@ -0,0 +1,299 @@
+//! Reconnecting to the device after the ADB server or the emulator went away.
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+use crate::error::{AGError, AGResult};
+
+use super::ADB;
+
+/// How `ADB` recovers a dropped connection, set with `AdbBuilder::with_reconnect`. Only applies to
+/// `Connection::Server`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    restart_server: bool,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(8),
+            restart_server: true,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts;
+        self
+    }
+
+    /// Wait before the first attempt, doubled after each failed one up to `max`.
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Whether to run `adb start-server` when the server does not accept connections.
+    pub fn with_restart_server(mut self, restart: bool) -> Self {
+        self.restart_server = restart;
+        self
+    }
+
+    /// Wait before attempt `attempt`, counting from 0.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt).unwrap_or(u32::MAX);
+        self.initial_backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+/// Errors after which the server or the device has to be reached again before anything can work.
+pub(crate) fn is_connection_lost(error: &AGError) -> bool {
+    use std::io::ErrorKind::*;
+    match error {
+        AGError::Io(e) => matches!(
+            e.kind(),
+            ConnectionRefused | ConnectionReset | ConnectionAborted | BrokenPipe | NotConnected | UnexpectedEof
+        ),
+        AGError::DeviceNotFound { .. } | AGError::DeviceOffline { .. } => true,
+        _ => false,
+    }
+}
+
+impl ADB {
+    /// Opens a new stream to the server, starting it if the policy allows, and `host:connect`s the target again.
+    /// Gives up after the policy's attempts with the last error. Agents, forwards and cached device state do not
+    /// survive a device restart and are dropped; the agents and forwards are named by `take_lost_sessions`.
+    pub fn reconnect(&mut self) -> AGResult<()> {
+        let policy = self.reconnect.unwrap_or_default();
+        self.reconnect_within(&policy, &mut 0)
+    }
+
+    /// Reconnects with the attempts of `policy` left after `attempt`, which counts the ones used.
+    fn reconnect_within(&mut self, policy: &ReconnectPolicy, attempt: &mut u32) -> AGResult<()> {
+        let mut last = AGError::Custom("no reconnect attempts left".to_string());
+        while *attempt < policy.max_attempts {
+            thread::sleep(policy.backoff(*attempt));
+            *attempt += 1;
+            match self.try_reconnect(policy) {
+                Err(e) if is_connection_lost(&e) => last = e,
+                result => return result,
+            }
+        }
+        Err(last)
+    }
+
+    fn try_reconnect(&mut self, policy: &ReconnectPolicy) -> AGResult<()> {
+        let stream = match TcpStream::connect(&self.server_addr) {
+            Ok(stream) => stream,
+            Err(_) if policy.restart_server => {
+                ADB::start_daemon(&self.bin_path)?;
+                TcpStream::connect(&self.server_addr)?
+            }
+            Err(e) => return Err(e.into()),
+        };
+        stream.set_read_timeout(self.stream.read_timeout()?)?;
+        stream.set_write_timeout(self.stream.write_timeout()?)?;
+        self.stream = stream;
+        if self.connect_target {
+            let target = self.target.clone();
+            self.connect(&target)?;
+        } else {
+            self.features = None;
+            self.display = None;
+            self.profile = None;
+        }
+        self.transport()?;
+        self.reset()?;
+        let lost = self.drop_sessions();
+        self.lost_sessions.extend(lost);
+        Ok(())
+    }
+
+    /// Agents and forwards dropped by reconnects since the last call, e.g. `minitouch` or `forward tcp:27183`. Touches
+    /// and screenshots fall back to `input` and `screencap` until they are set up again.
+    pub fn take_lost_sessions(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.lost_sessions)
+    }
+
+    /// Forgets the agents and forwards of the old connection and names them.
+    fn drop_sessions(&mut self) -> Vec<String> {
+        let mut lost = Vec::new();
+        for (name, active) in [
+            ("minitouch", self.minitouch.take().is_some()),
+            ("minicap", self.minicap.take().is_some()),
+            ("scrcpy", self.scrcpy.take().is_some()),
+        ] {
+            if active {
+                lost.push(name.to_string());
+            }
+        }
+        lost.extend(std::mem::take(&mut self.forwards).iter().map(|f| format!("forward {}", f)));
+        lost.extend(std::mem::take(&mut self.reverses).iter().map(|r| format!("reverse {}", r)));
+        lost
+    }
+
+    /// Runs an operation that is safe to repeat, reconnecting and running it again while the connection keeps dropping.
+    /// The policy's attempts are shared by all reconnects of one call.
+    pub(crate) fn retry_idempotent<T>(&mut self, mut f: impl FnMut(&mut Self) -> AGResult<T>) -> AGResult<T> {
+        let Some(policy) = self.reconnect else {
+            return f(self);
+        };
+        let mut attempt = 0;
+        loop {
+            match f(self) {
+                Err(e) if is_connection_lost(&e) && attempt < policy.max_attempts => self.reconnect_within(&policy, &mut attempt)?,
+                result => return result,
+            }
+        }
+    }
+
+    /// Runs an operation that may have taken effect before the connection dropped. It is not repeated; the connection
+    /// is restored for the next call and the operation reported as `AGError::Interrupted`, or as
+    /// `AGError::SessionsLost` if the reconnect dropped agents or forwards the operation may have relied on.
+    pub(crate) fn run_once<T>(&mut self, operation: &str, f: impl FnOnce(&mut Self) -> AGResult<T>) -> AGResult<T> {
+        match f(self) {
+            Err(e) if self.reconnect.is_some() && is_connection_lost(&e) => {
+                self.reconnect()?;
+                let lost = self.take_lost_sessions();
+                if !lost.is_empty() {
+                    return Err(AGError::SessionsLost {
+                        serial: self.target.clone(),
+                        lost,
+                    });
+                }
+                Err(AGError::Interrupted {
+                    serial: self.target.clone(),
+                    operation: operation.to_string(),
+                })
+            }
+            result => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::display::ROTATION_COMMAND;
+    use super::super::forward::ForwardSpec;
+    use super::super::mock::MockAdbServer;
+    use super::*;
+    use crate::controller::{AdbBuilder, Controller};
+
+    fn quick() -> ReconnectPolicy {
+        ReconnectPolicy::new()
+            .with_max_attempts(3)
+            .with_backoff(Duration::from_millis(1), Duration::from_millis(4))
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let policy = ReconnectPolicy::new().with_backoff(Duration::from_millis(100), Duration::from_millis(500));
+        let waits: Vec<_> = (0..5).map(|a| policy.backoff(a).as_millis()).collect();
+        assert_eq!(waits, [100, 200, 400, 500, 500]);
+        assert_eq!(policy.backoff(40), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn idempotent_calls_are_retried() {
+        let server = MockAdbServer::start()
+            .with_reply("wm size", "Physical size: 1080x1920\n")
+            .with_reply(ROTATION_COMMAND, "    SurfaceOrientation: 0\n");
+        let mut adb = AdbBuilder::new()
+            .with_addr(server.addr())
+            .with_target("127.0.0.1:7555")
+            .with_reconnect(quick())
+            .build()
+            .unwrap();
+        server.set_device_state("127.0.0.1:7555", None);
+        assert_eq!(adb.get_resolution().unwrap(), (1080, 1920));
+        let connects = server.requests().iter().filter(|r| r.starts_with("host:connect:")).count();
+        assert_eq!(connects, 2);
+    }
+
+    #[test]
+    fn retries_share_the_attempts() {
+        let server = MockAdbServer::start();
+        let mut adb = AdbBuilder::new()
+            .with_addr(server.addr())
+            .with_target("127.0.0.1:7555")
+            .with_reconnect(quick())
+            .build()
+            .unwrap();
+        server.set_device_state("127.0.0.1:7555", Some("offline"));
+        assert!(matches!(adb.get_resolution(), Err(AGError::DeviceOffline { .. })));
+        let connects = server.requests().iter().filter(|r| r.starts_with("host:connect:")).count();
+        assert_eq!(connects, 1 + 3);
+    }
+
+    #[test]
+    fn lost_forwards_are_reported() {
+        let server = MockAdbServer::start()
+            .with_reply("wm size", "Physical size: 1080x1920\n")
+            .with_reply(ROTATION_COMMAND, "    SurfaceOrientation: 0\n");
+        let mut adb = AdbBuilder::new()
+            .with_addr(server.addr())
+            .with_target("127.0.0.1:7555")
+            .with_reconnect(quick())
+            .build()
+            .unwrap();
+        let local = adb
+            .forward(&ForwardSpec::Tcp(0), &ForwardSpec::LocalAbstract("minitouch".to_string()))
+            .unwrap();
+        server.set_device_state("127.0.0.1:7555", None);
+        assert_eq!(adb.get_resolution().unwrap(), (1080, 1920));
+        assert!(adb.forwards.is_empty());
+        assert_eq!(adb.take_lost_sessions(), [format!("forward {}", local)]);
+        assert!(adb.take_lost_sessions().is_empty());
+
+        let local = adb
+            .forward(&ForwardSpec::Tcp(0), &ForwardSpec::LocalAbstract("scrcpy".to_string()))
+            .unwrap();
+        server.set_device_state("127.0.0.1:7555", None);
+        match adb.click(1, 2) {
+            Err(AGError::SessionsLost { lost, .. }) => assert_eq!(lost, [format!("forward {}", local)]),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(adb.take_lost_sessions().is_empty());
+    }
+
+    #[test]
+    fn other_calls_are_reported() {
+        let server = MockAdbServer::start();
+        let mut adb = AdbBuilder::new()
+            .with_addr(server.addr())
+            .with_target("127.0.0.1:7555")
+            .with_reconnect(quick())
+            .build()
+            .unwrap();
+        server.set_device_state("127.0.0.1:7555", None);
+        assert!(matches!(adb.click(1, 2), Err(AGError::Interrupted { operation, .. }) if operation == "click"));
+        adb.click(1, 2).unwrap();
+        let taps = server.requests().iter().filter(|r| r.contains("input tap 1 2")).count();
+        assert_eq!(taps, 1);
+
+        server.set_device_state("127.0.0.1:7555", Some("offline"));
+        assert!(matches!(adb.click(1, 2), Err(AGError::DeviceOffline { .. })));
+        let connects = server.requests().iter().filter(|r| r.starts_with("host:connect:")).count();
+        assert_eq!(connects, 2 + 3);
+    }
+
+    #[test]
+    fn without_policy_errors_pass_through() {
+        let server = MockAdbServer::start();
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).with_target("127.0.0.1:7555").build().unwrap();
+        server.set_device_state("127.0.0.1:7555", None);
+        assert!(matches!(adb.click(1, 2), Err(AGError::DeviceNotFound { .. })));
+    }
+}
@@ -0,0 +1,587 @@
+//! Client for the control socket of a scrcpy 2.x server: control messages go to the device, device messages
+//! (clipboard contents and acknowledgements) come back. All integers are big-endian.
+//!
+//! The server has to run with `tunnel_forward=true video=false audio=false send_dummy_byte=false
+//! send_device_meta=false`, so that the control socket is the only one and carries nothing but messages.
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use crate::error::{AGError, AGResult};
+
+use super::{ForwardSpec, ADB};
+
+/// `KeyEvent` actions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyAction {
+    Down = 0,
+    Up = 1,
+}
+
+/// `MotionEvent` actions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TouchAction {
+    Down = 0,
+    Up = 1,
+    Move = 2,
+}
+
+/// Key the device presses before sending its clipboard with `ControlMessage::GetClipboard`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyKey {
+    None = 0,
+    Copy = 1,
+    Cut = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScreenPowerMode {
+    Off = 0,
+    Normal = 2,
+}
+
+/// A point together with the screen size it refers to. The server drops events whose size does not match the
+/// current one, e.g. after a rotation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub x: u32,
+    pub y: u32,
+    pub screen_width: u16,
+    pub screen_height: u16,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControlMessage {
+    InjectKeycode {
+        action: KeyAction,
+        keycode: u32,
+        repeat: u32,
+        meta_state: u32,
+    },
+    InjectText(String),
+    InjectTouch {
+        action: TouchAction,
+        pointer_id: u64,
+        position: Position,
+        /// 0.0..=1.0
+        pressure: f32,
+        action_button: u32,
+        buttons: u32,
+    },
+    InjectScroll {
+        position: Position,
+        /// Notches, -16.0..=16.0.
+        hscroll: f32,
+        vscroll: f32,
+        buttons: u32,
+    },
+    /// Back if the screen is on, power otherwise.
+    BackOrScreenOn(KeyAction),
+    ExpandNotificationPanel,
+    ExpandSettingsPanel,
+    CollapsePanels,
+    GetClipboard(CopyKey),
+    /// `sequence` 0 asks for no `DeviceMessage::AckClipboard`.
+    SetClipboard {
+        sequence: u64,
+        text: String,
+        paste: bool,
+    },
+    SetScreenPowerMode(ScreenPowerMode),
+    RotateDevice,
+}
+
+/// 0.0..=1.0 as 16-bit fixed point, 1.0 mapping to 0xffff.
+fn u16_fixed(value: f32) -> u16 {
+    let value = value.clamp(0.0, 1.0);
+    if value >= 1.0 {
+        0xffff
+    } else {
+        (value * 65536.0) as u16
+    }
+}
+
+/// -1.0..=1.0 as signed 16-bit fixed point, 1.0 mapping to 0x7fff.
+fn i16_fixed(value: f32) -> i16 {
+    let value = value.clamp(-1.0, 1.0);
+    if value >= 1.0 {
+        0x7fff
+    } else {
+        (value * 32768.0) as i16
+    }
+}
+
+impl Position {
+    fn write(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.x.to_be_bytes());
+        buf.extend_from_slice(&self.y.to_be_bytes());
+        buf.extend_from_slice(&self.screen_width.to_be_bytes());
+        buf.extend_from_slice(&self.screen_height.to_be_bytes());
+    }
+}
+
+impl ControlMessage {
+    /// Largest message the server accepts.
+    pub const MAX_SIZE: usize = 1 << 18;
+    /// Longest text for `InjectText`.
+    pub const INJECT_TEXT_MAX_LENGTH: usize = 300;
+    /// `pointer_id` of the mouse; fingers use any other value.
+    pub const POINTER_ID_MOUSE: u64 = u64::MAX;
+
+    fn kind(&self) -> u8 {
+        match self {
+            ControlMessage::InjectKeycode { .. } => 0,
+            ControlMessage::InjectText(_) => 1,
+            ControlMessage::InjectTouch { .. } => 2,
+            ControlMessage::InjectScroll { .. } => 3,
+            ControlMessage::BackOrScreenOn(_) => 4,
+            ControlMessage::ExpandNotificationPanel => 5,
+            ControlMessage::ExpandSettingsPanel => 6,
+            ControlMessage::CollapsePanels => 7,
+            ControlMessage::GetClipboard(_) => 8,
+            ControlMessage::SetClipboard { .. } => 9,
+            ControlMessage::SetScreenPowerMode(_) => 10,
+            ControlMessage::RotateDevice => 11,
+        }
+    }
+
+    /// The wire form. Fails with `PayloadTooLarge` for text the server would reject.
+    pub fn serialize(&self) -> AGResult<Vec<u8>> {
+        let mut buf = vec![self.kind()];
+        match self {
+            ControlMessage::InjectKeycode {
+                action,
+                keycode,
+                repeat,
+                meta_state,
+            } => {
+                buf.push(*action as u8);
+                buf.extend_from_slice(&keycode.to_be_bytes());
+                buf.extend_from_slice(&repeat.to_be_bytes());
+                buf.extend_from_slice(&meta_state.to_be_bytes());
+            }
+            ControlMessage::InjectText(text) => {
+                if text.len() > Self::INJECT_TEXT_MAX_LENGTH {
+                    return Err(AGError::PayloadTooLarge(text.len()));
+                }
+                buf.extend_from_slice(&(text.len() as u32).to_be_bytes());
+                buf.extend_from_slice(text.as_bytes());
+            }
+            ControlMessage::InjectTouch {
+                action,
+                pointer_id,
+                position,
+                pressure,
+                action_button,
+                buttons,
+            } => {
+                buf.push(*action as u8);
+                buf.extend_from_slice(&pointer_id.to_be_bytes());
+                position.write(&mut buf);
+                buf.extend_from_slice(&u16_fixed(*pressure).to_be_bytes());
+                buf.extend_from_slice(&action_button.to_be_bytes());
+                buf.extend_from_slice(&buttons.to_be_bytes());
+            }
+            ControlMessage::InjectScroll {
+                position,
+                hscroll,
+                vscroll,
+                buttons,
+            } => {
+                position.write(&mut buf);
+                buf.extend_from_slice(&i16_fixed(hscroll / 16.0).to_be_bytes());
+                buf.extend_from_slice(&i16_fixed(vscroll / 16.0).to_be_bytes());
+                buf.extend_from_slice(&buttons.to_be_bytes());
+            }
+            ControlMessage::BackOrScreenOn(action) => buf.push(*action as u8),
+            ControlMessage::GetClipboard(copy_key) => buf.push(*copy_key as u8),
+            ControlMessage::SetClipboard { sequence, text, paste } => {
+                if 14 + text.len() > Self::MAX_SIZE {
+                    return Err(AGError::PayloadTooLarge(text.len()));
+                }
+                buf.extend_from_slice(&sequence.to_be_bytes());
+                buf.push(*paste as u8);
+                buf.extend_from_slice(&(text.len() as u32).to_be_bytes());
+                buf.extend_from_slice(text.as_bytes());
+            }
+            ControlMessage::SetScreenPowerMode(mode) => buf.push(*mode as u8),
+            ControlMessage::ExpandNotificationPanel
+            | ControlMessage::ExpandSettingsPanel
+            | ControlMessage::CollapsePanels
+            | ControlMessage::RotateDevice => {}
+        }
+        Ok(buf)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceMessage {
+    Clipboard(String),
+    AckClipboard(u64),
+    UhidOutput { id: u16, data: Vec<u8> },
+}
+
+impl DeviceMessage {
+    /// Parses one message off the front of `buf` and returns it with the number of bytes it took, or `None` if `buf`
+    /// does not hold a whole message yet.
+    pub fn decode(buf: &[u8]) -> AGResult<Option<(Self, usize)>> {
+        let Some(&kind) = buf.first() else {
+            return Ok(None);
+        };
+        let be = |range: std::ops::Range<usize>| buf.get(range).map(|b| b.iter().fold(0u64, |n, &b| n << 8 | b as u64));
+        let message = match kind {
+            0 => {
+                let Some(len) = be(1..5) else { return Ok(None) };
+                let end = 5 + len as usize;
+                if end > ControlMessage::MAX_SIZE {
+                    return Err(AGError::PayloadTooLarge(len as usize));
+                }
+                let Some(text) = buf.get(5..end) else { return Ok(None) };
+                (DeviceMessage::Clipboard(String::from_utf8_lossy(text).to_string()), end)
+            }
+            1 => {
+                let Some(sequence) = be(1..9) else { return Ok(None) };
+                (DeviceMessage::AckClipboard(sequence), 9)
+            }
+            2 => {
+                let (Some(id), Some(size)) = (be(1..3), be(3..5)) else {
+                    return Ok(None);
+                };
+                let end = 5 + size as usize;
+                let Some(data) = buf.get(5..end) else { return Ok(None) };
+                (
+                    DeviceMessage::UhidOutput {
+                        id: id as u16,
+                        data: data.to_vec(),
+                    },
+                    end,
+                )
+            }
+            _ => return Err(AGError::Decode),
+        };
+        Ok(Some(message))
+    }
+
+    pub fn read(stream: &mut impl Read) -> AGResult<Self> {
+        let mut buf = Vec::new();
+        let mut byte = [0];
+        loop {
+            if let Some((message, _)) = Self::decode(&buf)? {
+                return Ok(message);
+            }
+            stream.read_exact(&mut byte)?;
+            buf.push(byte[0]);
+        }
+    }
+}
+
+/// A connected control socket, with helpers for the messages scripts need most.
+#[derive(Debug)]
+pub struct ScrcpyControl<S = TcpStream> {
+    stream: S,
+    screen: (u16, u16),
+    sequence: u64,
+}
+
+impl<S: Read + Write> ScrcpyControl<S> {
+    /// `screen_size` has to match the device's current one, see `Position`.
+    pub fn new(stream: S, screen_size: (u32, u32)) -> Self {
+        ScrcpyControl {
+            stream,
+            screen: (screen_size.0 as u16, screen_size.1 as u16),
+            sequence: 0,
+        }
+    }
+
+    pub fn set_screen_size(&mut self, screen_size: (u32, u32)) {
+        self.screen = (screen_size.0 as u16, screen_size.1 as u16);
+    }
+
+    pub fn send(&mut self, message: &ControlMessage) -> AGResult<()> {
+        self.stream.write_all(&message.serialize()?)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    /// Reads the next device message; clipboard changes on the device arrive unasked.
+    pub fn read_message(&mut self) -> AGResult<DeviceMessage> {
+        DeviceMessage::read(&mut self.stream)
+    }
+
+    fn position(&self, x: u32, y: u32) -> Position {
+        Position {
+            x,
+            y,
+            screen_width: self.screen.0,
+            screen_height: self.screen.1,
+        }
+    }
+
+    pub fn touch(&mut self, action: TouchAction, pointer_id: u64, x: u32, y: u32, pressure: f32) -> AGResult<()> {
+        self.send(&ControlMessage::InjectTouch {
+            action,
+            pointer_id,
+            position: self.position(x, y),
+            pressure,
+            action_button: 0,
+            buttons: 0,
+        })
+    }
+
+    pub fn tap(&mut self, x: u32, y: u32) -> AGResult<()> {
+        self.touch(TouchAction::Down, 0, x, y, 1.0)?;
+        self.touch(TouchAction::Up, 0, x, y, 0.0)
+    }
+
+    /// Moves in steps of about 16 ms; the timing is done on the host.
+    pub fn swipe(&mut self, from: (u32, u32), to: (u32, u32), duration: Duration) -> AGResult<()> {
+        self.drag(from, to, Duration::ZERO, duration)
+    }
+
+    /// `swipe` that holds the first touch for `hold` before moving.
+    pub fn drag(&mut self, from: (u32, u32), to: (u32, u32), hold: Duration, duration: Duration) -> AGResult<()> {
+        let steps = (duration.as_millis() / 16).max(1) as i64;
+        self.touch(TouchAction::Down, 0, from.0, from.1, 1.0)?;
+        std::thread::sleep(hold);
+        for i in 1..=steps {
+            std::thread::sleep(duration / steps as u32);
+            let lerp = |a: u32, b: u32| (a as i64 + (b as i64 - a as i64) * i / steps) as u32;
+            self.touch(TouchAction::Move, 0, lerp(from.0, to.0), lerp(from.1, to.1), 1.0)?;
+        }
+        self.touch(TouchAction::Up, 0, to.0, to.1, 0.0)
+    }
+
+    pub fn scroll(&mut self, x: u32, y: u32, hscroll: f32, vscroll: f32) -> AGResult<()> {
+        self.send(&ControlMessage::InjectScroll {
+            position: self.position(x, y),
+            hscroll,
+            vscroll,
+            buttons: 0,
+        })
+    }
+
+    /// Presses and releases `keycode` with `meta_state` (`AMETA_*` bits) held.
+    pub fn key(&mut self, keycode: u32, meta_state: u32) -> AGResult<()> {
+        for action in [KeyAction::Down, KeyAction::Up] {
+            self.send(&ControlMessage::InjectKeycode {
+                action,
+                keycode,
+                repeat: 0,
+                meta_state,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Types `text` through the device's key character map, so only characters it has keys for arrive.
+    pub fn text(&mut self, text: &str) -> AGResult<()> {
+        self.send(&ControlMessage::InjectText(text.to_string()))
+    }
+
+    /// Waits for the device clipboard, skipping other device messages.
+    pub fn get_clipboard(&mut self) -> AGResult<String> {
+        self.send(&ControlMessage::GetClipboard(CopyKey::None))?;
+        loop {
+            if let DeviceMessage::Clipboard(text) = self.read_message()? {
+                return Ok(text);
+            }
+        }
+    }
+
+    /// Sets the device clipboard, optionally pasting it into the focused field, and waits until the device confirmed.
+    pub fn set_clipboard(&mut self, text: &str, paste: bool) -> AGResult<()> {
+        self.sequence += 1;
+        let sequence = self.sequence;
+        self.send(&ControlMessage::SetClipboard {
+            sequence,
+            text: text.to_string(),
+            paste,
+        })?;
+        while self.read_message()? != DeviceMessage::AckClipboard(sequence) {}
+        Ok(())
+    }
+
+    /// Turns the display off or on while the device keeps running, unlike the power key.
+    pub fn set_screen_power(&mut self, on: bool) -> AGResult<()> {
+        let mode = if on { ScreenPowerMode::Normal } else { ScreenPowerMode::Off };
+        self.send(&ControlMessage::SetScreenPowerMode(mode))
+    }
+}
+
+impl ADB {
+    /// Connects to the control socket of a scrcpy server listening on `localabstract:<socket>`, `scrcpy` unless it
+    /// was started with an `scid`. Taps, swipes, drags, keys and text go through it afterwards; minitouch still takes
+    /// precedence for touches when enabled.
+    pub fn enable_scrcpy(&mut self, socket: &str) -> AGResult<()> {
+        let remote = ForwardSpec::LocalAbstract(socket.to_string());
+        let ForwardSpec::Tcp(port) = self.forward(&ForwardSpec::Tcp(0), &remote)? else {
+            return Err(AGError::Decode);
+        };
+        let stream = TcpStream::connect((self.stream.peer_addr()?.ip(), port))?;
+        stream.set_read_timeout(self.stream.read_timeout()?)?;
+        let size = self.display_info()?.effective_size();
+        self.scrcpy = Some(ScrcpyControl::new(stream, size));
+        Ok(())
+    }
+
+    pub fn disable_scrcpy(&mut self) {
+        self.scrcpy = None;
+    }
+
+    pub fn scrcpy(&mut self) -> Option<&mut ScrcpyControl> {
+        self.scrcpy.as_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// A server that writes `replies` and records everything it receives until the client disconnects.
+    fn recording_server(replies: Vec<u8>) -> (String, thread::JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(&replies).unwrap();
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).unwrap();
+            received
+        });
+        (addr, handle)
+    }
+
+    #[test]
+    fn messages_are_byte_exact() {
+        let (addr, server) = recording_server(Vec::new());
+        let mut control = ScrcpyControl::new(TcpStream::connect(addr).unwrap(), (1080, 1920));
+        let position = Position {
+            x: 260,
+            y: 1026,
+            screen_width: 1080,
+            screen_height: 1920,
+        };
+        let messages = [
+            ControlMessage::InjectKeycode {
+                action: KeyAction::Up,
+                keycode: 66,
+                repeat: 5,
+                meta_state: 0x41,
+            },
+            ControlMessage::InjectText("hello".to_string()),
+            ControlMessage::InjectTouch {
+                action: TouchAction::Down,
+                pointer_id: 0x1234567887654321,
+                position: Position { x: 100, y: 200, ..position },
+                pressure: 1.0,
+                action_button: 1,
+                buttons: 1,
+            },
+            ControlMessage::InjectScroll {
+                position,
+                hscroll: 16.0,
+                vscroll: -16.0,
+                buttons: 1,
+            },
+            ControlMessage::BackOrScreenOn(KeyAction::Up),
+            ControlMessage::ExpandNotificationPanel,
+            ControlMessage::GetClipboard(CopyKey::Copy),
+            ControlMessage::SetClipboard {
+                sequence: 0x0102030405060708,
+                text: "hi".to_string(),
+                paste: true,
+            },
+            ControlMessage::SetScreenPowerMode(ScreenPowerMode::Normal),
+            ControlMessage::RotateDevice,
+        ];
+        for message in &messages {
+            control.send(message).unwrap();
+        }
+        control.tap(1, 2).unwrap();
+        drop(control);
+
+        #[rustfmt::skip]
+        let expected: Vec<u8> = [
+            &[0, 1, 0, 0, 0, 66, 0, 0, 0, 5, 0, 0, 0, 0x41][..],
+            &[1, 0, 0, 0, 5, b'h', b'e', b'l', b'l', b'o'],
+            &[2, 0, 0x12, 0x34, 0x56, 0x78, 0x87, 0x65, 0x43, 0x21, 0, 0, 0, 100, 0, 0, 0, 200, 0x04, 0x38, 0x07, 0x80, 0xff, 0xff, 0, 0, 0, 1, 0, 0, 0, 1],
+            &[3, 0, 0, 1, 4, 0, 0, 4, 2, 0x04, 0x38, 0x07, 0x80, 0x7f, 0xff, 0x80, 0x00, 0, 0, 0, 1],
+            &[4, 1],
+            &[5],
+            &[8, 1],
+            &[9, 1, 2, 3, 4, 5, 6, 7, 8, 1, 0, 0, 0, 2, b'h', b'i'],
+            &[10, 2],
+            &[11],
+            &[2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 2, 0x04, 0x38, 0x07, 0x80, 0xff, 0xff, 0, 0, 0, 0, 0, 0, 0, 0],
+            &[2, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 2, 0x04, 0x38, 0x07, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        ]
+        .concat();
+        assert_eq!(server.join().unwrap(), expected);
+        assert!(matches!(
+            ControlMessage::InjectText("x".repeat(301)).serialize(),
+            Err(AGError::PayloadTooLarge(301))
+        ));
+    }
+
+    #[test]
+    fn drags_are_injected_touches() {
+        use super::super::mock::MockAdbServer;
+        use crate::controller::{AdbBuilder, Controller};
+        let mock = MockAdbServer::start();
+        let mut adb = AdbBuilder::new().with_addr(mock.addr()).with_target("emulator-5554").build().unwrap();
+        let (addr, server) = recording_server(Vec::new());
+        adb.scrcpy = Some(ScrcpyControl::new(TcpStream::connect(addr).unwrap(), (1080, 1920)));
+        let started = std::time::Instant::now();
+        adb.drag(10, 20, 30, 60, Duration::from_millis(50), Duration::from_millis(32)).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(82));
+        adb.disable_scrcpy();
+        let received = server.join().unwrap();
+        let touches: Vec<_> = received
+            .chunks(32)
+            .map(|m| {
+                (
+                    m[0],
+                    m[1],
+                    u32::from_be_bytes([m[10], m[11], m[12], m[13]]),
+                    u32::from_be_bytes([m[14], m[15], m[16], m[17]]),
+                )
+            })
+            .collect();
+        assert_eq!(touches, [(2, 0, 10, 20), (2, 2, 20, 40), (2, 2, 30, 60), (2, 1, 30, 60)]);
+        assert!(!mock.requests().iter().any(|r| r.contains("input")));
+    }
+
+    #[test]
+    fn device_messages_are_parsed() {
+        assert_eq!(DeviceMessage::decode(&[0, 0, 0, 0, 3, b'a', b'b']).unwrap(), None);
+        assert_eq!(
+            DeviceMessage::decode(&[0, 0, 0, 0, 3, b'a', b'b', b'c', 1]).unwrap(),
+            Some((DeviceMessage::Clipboard("abc".to_string()), 8))
+        );
+        assert_eq!(
+            DeviceMessage::decode(&[2, 0, 7, 0, 2, 0xaa, 0xbb]).unwrap(),
+            Some((
+                DeviceMessage::UhidOutput {
+                    id: 7,
+                    data: vec![0xaa, 0xbb]
+                },
+                7
+            ))
+        );
+        assert!(DeviceMessage::decode(&[9]).is_err());
+
+        let mut replies = vec![0, 0, 0, 0, 6];
+        replies.extend_from_slice("剪贴".as_bytes());
+        replies.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0, 1]);
+        let (addr, server) = recording_server(replies);
+        let mut control = ScrcpyControl::new(TcpStream::connect(addr).unwrap(), (1080, 1920));
+        assert_eq!(control.get_clipboard().unwrap(), "剪贴");
+        control.set_clipboard("粘贴", true).unwrap();
+        drop(control);
+        let mut expected = vec![8, 0, 9, 0, 0, 0, 0, 0, 0, 0, 1, 1, 0, 0, 0, 6];
+        expected.extend_from_slice("粘贴".as_bytes());
+        assert_eq!(server.join().unwrap(), expected);
+    }
+}
@@ -0,0 +1,206 @@
+//! Screenshot capture with `screencap`, either PNG-encoded on the device or as the raw framebuffer dump.
+use std::io::Read;
+
+use flate2::read::GzDecoder;
+use image::RgbaImage;
+
+use crate::error::{AGError, AGResult};
+
+use super::ADB;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScreenshotMode {
+    /// `screencap -p`: slow device-side PNG encode, but the smallest transfer.
+    #[default]
+    Png,
+    /// Plain `screencap`: header plus raw pixels, no encode or decode at all.
+    Raw,
+    /// `screencap | gzip -1`: raw pixels compressed for slow links such as Wi-Fi debugging.
+    RawGzip,
+    /// The `framebuffer:` service, falling back to `Raw` on devices that refuse it.
+    Framebuffer,
+}
+
+/// `android::PixelFormat` values that `screencap` may emit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Rgba8888,
+    Rgbx8888,
+    Rgb888,
+    Rgb565,
+    Bgra8888,
+}
+
+impl PixelFormat {
+    pub fn from_raw(format: u32) -> Option<Self> {
+        match format {
+            1 => Some(PixelFormat::Rgba8888),
+            2 => Some(PixelFormat::Rgbx8888),
+            3 => Some(PixelFormat::Rgb888),
+            4 => Some(PixelFormat::Rgb565),
+            5 => Some(PixelFormat::Bgra8888),
+            _ => None,
+        }
+    }
+
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Rgba8888 | PixelFormat::Rgbx8888 | PixelFormat::Bgra8888 => 4,
+            PixelFormat::Rgb888 => 3,
+            PixelFormat::Rgb565 => 2,
+        }
+    }
+
+    /// Converts tightly packed pixels in this format to RGBA.
+    pub fn to_rgba(self, width: u32, height: u32, pixels: &[u8]) -> AGResult<RgbaImage> {
+        let bpp = self.bytes_per_pixel();
+        let count = width as usize * height as usize;
+        let pixels = pixels.get(..count * bpp).ok_or(AGError::Decode)?;
+        let rgba = match self {
+            PixelFormat::Rgba8888 => pixels.to_vec(),
+            PixelFormat::Rgbx8888 => pixels.chunks_exact(4).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+            PixelFormat::Rgb888 => pixels.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+            PixelFormat::Bgra8888 => pixels.chunks_exact(4).flat_map(|p| [p[2], p[1], p[0], p[3]]).collect(),
+            PixelFormat::Rgb565 => pixels
+                .chunks_exact(2)
+                .flat_map(|p| {
+                    let v = u16::from_le_bytes([p[0], p[1]]);
+                    let r = ((v >> 11) & 0x1f) as u8;
+                    let g = ((v >> 5) & 0x3f) as u8;
+                    let b = (v & 0x1f) as u8;
+                    [(r << 3) | (r >> 2), (g << 2) | (g >> 4), (b << 3) | (b >> 2), 255]
+                })
+                .collect(),
+        };
+        RgbaImage::from_raw(width, height, rgba).ok_or(AGError::Decode)
+    }
+}
+
+/// Header of a raw `screencap` dump: width, height and pixel format as little-endian u32s, followed on Android 9+ by
+/// the colour space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawHeader {
+    pub width: u32,
+    pub height: u32,
+    pub format: PixelFormat,
+    pub color_space: Option<u32>,
+}
+
+impl RawHeader {
+    /// Parses the header, telling the 12- and 16-byte layouts apart by the payload size. Returns the header and its length.
+    pub fn parse(data: &[u8]) -> AGResult<(Self, usize)> {
+        let field = |i: usize| {
+            data.get(i * 4..i * 4 + 4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .ok_or(AGError::Decode)
+        };
+        let (width, height) = (field(0)?, field(1)?);
+        let format = PixelFormat::from_raw(field(2)?).ok_or(AGError::Decode)?;
+        let size = width as usize * height as usize * format.bytes_per_pixel();
+        if data.len() == 12 + size {
+            return Ok((
+                RawHeader {
+                    width,
+                    height,
+                    format,
+                    color_space: None,
+                },
+                12,
+            ));
+        }
+        if data.len() >= 16 + size {
+            return Ok((
+                RawHeader {
+                    width,
+                    height,
+                    format,
+                    color_space: Some(field(3)?),
+                },
+                16,
+            ));
+        }
+        Err(AGError::Decode)
+    }
+}
+
+/// Decodes the output of plain `screencap`.
+pub fn decode_raw(data: &[u8]) -> AGResult<RgbaImage> {
+    let (header, offset) = RawHeader::parse(data)?;
+    header.format.to_rgba(header.width, header.height, &data[offset..])
+}
+
+impl ADB {
+    /// Takes a screenshot with the given `screencap` mode, regardless of the one configured on the builder.
+    pub fn screencap(&mut self, mode: ScreenshotMode) -> AGResult<RgbaImage> {
+        let command = match mode {
+            ScreenshotMode::Png => "screencap -p",
+            ScreenshotMode::Raw => "screencap",
+            ScreenshotMode::RawGzip => "screencap | gzip -1",
+            ScreenshotMode::Framebuffer => return self.framebuffer_or_screencap(),
+        };
+        let recv = self.exec(command)?;
+        if !recv.is_ok {
+            return Err(AGError::adb_fail(
+                &self.target,
+                &format!("exec:{}", command),
+                String::from_utf8_lossy(&recv.data).to_string(),
+            ));
+        }
+        match mode {
+            ScreenshotMode::Png => Ok(image::load_from_memory(&recv.data)?.to_rgba8()),
+            ScreenshotMode::RawGzip => {
+                let mut data = Vec::new();
+                GzDecoder::new(recv.data.as_slice()).read_to_end(&mut data)?;
+                decode_raw(&data)
+            }
+            _ => decode_raw(&recv.data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock::MockAdbServer;
+    use super::*;
+    use crate::controller::{AdbBuilder, Controller};
+
+    fn sample() -> RgbaImage {
+        RgbaImage::from_fn(5, 3, |x, y| image::Rgba([x as u8 * 40, y as u8 * 80, 7, 255]))
+    }
+
+    #[test]
+    fn decode_formats() {
+        let img = sample();
+        let mut v1 = vec![5, 0, 0, 0, 3, 0, 0, 0, 1, 0, 0, 0];
+        v1.extend_from_slice(img.as_raw());
+        assert_eq!(decode_raw(&v1).unwrap(), img);
+
+        let mut bgra = vec![5, 0, 0, 0, 3, 0, 0, 0, 5, 0, 0, 0, 1, 0, 0, 0];
+        bgra.extend(img.pixels().flat_map(|p| [p[2], p[1], p[0], p[3]]));
+        let (header, offset) = RawHeader::parse(&bgra).unwrap();
+        assert_eq!((header.format, header.color_space, offset), (PixelFormat::Bgra8888, Some(1), 16));
+        assert_eq!(decode_raw(&bgra).unwrap(), img);
+
+        let mut rgb565 = vec![1, 0, 0, 0, 1, 0, 0, 0, 4, 0, 0, 0];
+        rgb565.extend_from_slice(&0xf800u16.to_le_bytes());
+        assert_eq!(decode_raw(&rgb565).unwrap().get_pixel(0, 0), &image::Rgba([255, 0, 0, 255]));
+
+        assert!(decode_raw(&v1[..20]).is_err());
+    }
+
+    #[test]
+    fn builder_selects_mode() {
+        let img = sample();
+        let server = MockAdbServer::start().with_raw_screenshot(&img);
+        for mode in [ScreenshotMode::Raw, ScreenshotMode::RawGzip] {
+            let mut adb = AdbBuilder::new()
+                .with_addr(server.addr())
+                .with_target("emulator-5554")
+                .with_screenshot_mode(mode)
+                .build()
+                .unwrap();
+            assert_eq!(adb.screenshot().unwrap(), img);
+        }
+        assert!(server.requests().contains(&"exec:screencap | gzip -1".to_string()));
+    }
+}
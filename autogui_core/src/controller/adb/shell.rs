@@ -0,0 +1,266 @@
+//! Shell commands with separate stdout, stderr and exit code, via the `shell,v2,raw:` framed protocol.
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::time::Duration;
+
+use crate::error::{AGError, AGResult};
+
+use super::ADB;
+
+const ID_STDOUT: u8 = 1;
+const ID_STDERR: u8 = 2;
+const ID_EXIT: u8 = 3;
+const ID_CLOSE_STDIN: u8 = 4;
+
+/// Printed after the command on the legacy `shell:` path so the exit code can be recovered from the output.
+pub(crate) const EXIT_MARKER: &str = "\u{1}AG_EXIT:";
+
+pub(crate) fn legacy_command(cmd: &str) -> String {
+    format!("{}; echo -n \"{}$?\"", cmd, EXIT_MARKER)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShellOutput {
+    pub stdout: Vec<u8>,
+    /// Always empty on the legacy path, where stderr is interleaved into `stdout`.
+    pub stderr: Vec<u8>,
+    pub exit_code: i32,
+}
+
+impl ShellOutput {
+    pub fn success(&self) -> bool {
+        self.exit_code == 0
+    }
+
+    pub fn stdout_str(&self) -> String {
+        String::from_utf8_lossy(&self.stdout).to_string()
+    }
+
+    pub fn stderr_str(&self) -> String {
+        String::from_utf8_lossy(&self.stderr).to_string()
+    }
+
+    /// Turns a non-zero exit code into `AGError::ShellExit`.
+    pub fn check(self, cmd: &str) -> AGResult<Self> {
+        if self.success() {
+            return Ok(self);
+        }
+        let stderr = if self.stderr.is_empty() { self.stdout_str() } else { self.stderr_str() };
+        Err(AGError::ShellExit {
+            cmd: cmd.to_string(),
+            exit_code: self.exit_code,
+            stderr: stderr.trim().to_string(),
+        })
+    }
+
+    /// Decodes the v2 packet stream: a one-byte id, a little-endian u32 length, then the payload.
+    pub(crate) fn read_v2(stream: &mut impl Read) -> AGResult<Self> {
+        let mut output = ShellOutput::default();
+        let mut exit_code = None;
+        loop {
+            let mut header = [0u8; 5];
+            match stream.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            }
+            let length = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+            let mut payload = vec![0u8; length];
+            stream.read_exact(&mut payload)?;
+            match header[0] {
+                ID_STDOUT => output.stdout.extend_from_slice(&payload),
+                ID_STDERR => output.stderr.extend_from_slice(&payload),
+                ID_EXIT => {
+                    exit_code = payload.first().map(|&code| code as i32);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        output.exit_code = exit_code.ok_or_else(|| AGError::Custom("shell exited without status".to_string()))?;
+        Ok(output)
+    }
+
+    /// Splits the output of a `legacy_command` into the command's own output and its exit code.
+    pub(crate) fn from_legacy(mut data: Vec<u8>) -> AGResult<Self> {
+        let marker = EXIT_MARKER.as_bytes();
+        let position = data
+            .windows(marker.len())
+            .rposition(|w| w == marker)
+            .ok_or_else(|| AGError::Custom("shell exited without status".to_string()))?;
+        let exit_code = String::from_utf8_lossy(&data[position + marker.len()..])
+            .trim()
+            .parse()
+            .map_err(|_| AGError::Decode)?;
+        data.truncate(position);
+        Ok(ShellOutput {
+            stdout: data,
+            stderr: Vec::new(),
+            exit_code,
+        })
+    }
+}
+
+/// Output of a long-running `shell:` or `exec:` command, read straight off its socket.
+/// Dropping the handle closes the socket, which makes adbd kill the command.
+pub struct ShellStream {
+    reader: BufReader<TcpStream>,
+}
+
+impl ShellStream {
+    /// Streams start without a read timeout; set one to bound how long a `read` may block.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> AGResult<()> {
+        self.reader.get_ref().set_read_timeout(timeout)?;
+        Ok(())
+    }
+
+    pub fn read_timeout(&self) -> AGResult<Option<Duration>> {
+        Ok(self.reader.get_ref().read_timeout()?)
+    }
+}
+
+impl Read for ShellStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl BufRead for ShellStream {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.reader.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.reader.consume(amt)
+    }
+}
+
+impl Drop for ShellStream {
+    fn drop(&mut self) {
+        let _ = self.reader.get_ref().shutdown(Shutdown::Both);
+    }
+}
+
+impl ADB {
+    fn open_stream(&mut self, service: &str) -> AGResult<ShellStream> {
+        self.transport()?;
+        self.open_service(service)?;
+        let stream = self.detach()?;
+        stream.set_read_timeout(None)?;
+        Ok(ShellStream {
+            reader: BufReader::new(stream),
+        })
+    }
+
+    /// Starts `cmd` on the `shell:` service and returns its output as a stream, e.g. for `logcat` or `getevent`.
+    /// The `ADB` gets a new control stream right away and stays usable while the handle is alive.
+    pub fn shell_stream(&mut self, cmd: &str) -> AGResult<ShellStream> {
+        self.open_stream(&format!("shell:{}", cmd))
+    }
+
+    /// Like `shell_stream`, on the binary-safe `exec:` service.
+    pub fn exec_stream(&mut self, cmd: &str) -> AGResult<ShellStream> {
+        self.open_stream(&format!("exec:{}", cmd))
+    }
+
+    /// Runs `cmd` with `shell,v2,raw:` when the device advertises `shell_v2`, otherwise on the legacy `shell:` service.
+    pub fn shell_output(&mut self, cmd: &str) -> AGResult<ShellOutput> {
+        if self.has_feature("shell_v2")? {
+            self.shell_v2(cmd)
+        } else {
+            let command = legacy_command(cmd);
+            let recv = self.shell(&command)?;
+            if !recv.is_ok {
+                return Err(AGError::adb_fail(
+                    &self.target,
+                    &format!("shell:{}", command),
+                    String::from_utf8_lossy(&recv.data).to_string(),
+                ));
+            }
+            ShellOutput::from_legacy(recv.data)
+        }
+    }
+
+    pub fn shell_v2(&mut self, cmd: &str) -> AGResult<ShellOutput> {
+        let service = format!("shell,v2,raw:{}", cmd);
+        self.transport()?;
+        self.open_service(&service)?;
+        self.stream.write_all(&[ID_CLOSE_STDIN, 0, 0, 0, 0])?;
+        let output = ShellOutput::read_v2(&mut self.stream);
+        self.reset()?;
+        output.map_err(|e| e.in_service(&self.target, &service))
+    }
+
+    /// Runs `cmd` and fails with `AGError::ShellExit` if it exits non-zero.
+    pub fn shell_checked(&mut self, cmd: &str) -> AGResult<ShellOutput> {
+        self.shell_output(cmd)?.check(cmd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock::MockAdbServer;
+    use super::*;
+    use crate::controller::{AdbBuilder, Controller};
+
+    #[test]
+    fn v2_separates_streams() {
+        let server = MockAdbServer::start()
+            .with_features(&["shell_v2"])
+            .with_shell_reply("ls /x", "", "ls: /x: No such file\n", 1);
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).with_target("emulator-5554").build().unwrap();
+        let output = adb.shell_output("ls /x").unwrap();
+        assert_eq!(output.stderr_str(), "ls: /x: No such file\n");
+        assert_eq!(output.exit_code, 1);
+        assert!(server.requests().contains(&"shell,v2,raw:ls /x".to_string()));
+        match adb.shell_checked("ls /x") {
+            Err(AGError::ShellExit { exit_code: 1, stderr, .. }) => assert_eq!(stderr, "ls: /x: No such file"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn legacy_fallback_recovers_exit_code() {
+        let server = MockAdbServer::start()
+            .with_shell_reply("echo hi", "hi\n", "", 0)
+            .with_shell_reply("input tap 1 2", "", "locked\n", 137);
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).with_target("emulator-5554").build().unwrap();
+        let output = adb.shell_output("echo hi").unwrap();
+        assert_eq!(
+            output,
+            ShellOutput {
+                stdout: b"hi\n".to_vec(),
+                stderr: Vec::new(),
+                exit_code: 0
+            }
+        );
+        assert!(matches!(adb.click(1, 2), Err(AGError::ShellExit { exit_code: 137, .. })));
+    }
+
+    #[test]
+    fn stream_yields_lines_until_dropped() {
+        let server = MockAdbServer::start().with_endless_reply("logcat", "I/tag: line\n");
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).with_target("emulator-5554").build().unwrap();
+        let stream = adb.shell_stream("logcat").unwrap();
+        let lines: Vec<String> = stream.lines().take(3).map(|l| l.unwrap()).collect();
+        assert_eq!(lines, ["I/tag: line"; 3]);
+        assert!(adb.shell("echo hi").unwrap().is_ok);
+    }
+
+    #[test]
+    fn stream_ends_with_command() {
+        let server = MockAdbServer::start().with_reply("cat /a", "one\ntwo\n");
+        let mut adb = AdbBuilder::new().with_addr(server.addr()).with_target("emulator-5554").build().unwrap();
+        let mut data = String::new();
+        adb.exec_stream("cat /a").unwrap().read_to_string(&mut data).unwrap();
+        assert_eq!(data, "one\ntwo\n");
+    }
+
+    #[test]
+    fn read_v2_requires_exit_packet() {
+        let mut data: &[u8] = &[1, 2, 0, 0, 0, b'o', b'k'];
+        assert!(ShellOutput::read_v2(&mut data).is_err());
+        let mut data: &[u8] = &[1, 2, 0, 0, 0, b'o', b'k', 3, 1, 0, 0, 0, 0];
+        assert_eq!(ShellOutput::read_v2(&mut data).unwrap().stdout, b"ok");
+    }
+}
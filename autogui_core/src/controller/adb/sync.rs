@@ -0,0 +1,391 @@
+//! File transfer over the `sync:` service.
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::{AGError, AGResult};
+
+use super::ADB;
+
+const MAX_CHUNK: usize = 64 * 1024;
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileStat {
+    pub mode: u32,
+    pub size: u64,
+    pub mtime: i64,
+}
+
+impl FileStat {
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.mode & S_IFMT == S_IFREG
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub stat: FileStat,
+}
+
+fn read_u32(stream: &mut impl Read) -> AGResult<u32> {
+    let mut buf = [0u8; 4];
+    stream.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(stream: &mut impl Read) -> AGResult<u64> {
+    let mut buf = [0u8; 8];
+    stream.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_id(stream: &mut impl Read) -> AGResult<[u8; 4]> {
+    let mut id = [0u8; 4];
+    stream.read_exact(&mut id)?;
+    Ok(id)
+}
+
+fn write_request(stream: &mut impl Write, id: &[u8; 4], payload: &[u8]) -> AGResult<()> {
+    let length = u32::try_from(payload.len()).map_err(|_| AGError::Custom("sync payload too large".to_string()))?;
+    stream.write_all(id)?;
+    stream.write_all(&length.to_le_bytes())?;
+    stream.write_all(payload)?;
+    Ok(())
+}
+
+/// Reads the message following a `FAIL` id.
+fn read_fail(stream: &mut impl Read, serial: &str) -> AGResult<AGError> {
+    let length = read_u32(stream)? as usize;
+    let mut message = vec![0u8; length];
+    stream.read_exact(&mut message)?;
+    let message = String::from_utf8_lossy(&message).to_string();
+    Ok(AGError::AdbFail {
+        serial: serial.to_string(),
+        service: "sync:".to_string(),
+        message,
+    })
+}
+
+fn unexpected(id: [u8; 4]) -> AGError {
+    AGError::Custom(format!("unexpected sync reply {}", String::from_utf8_lossy(&id)))
+}
+
+/// Reads the body of a `STA2`/`DNT2` record after its id: error, dev, ino, mode, nlink, uid, gid, size, atime, mtime, ctime.
+fn read_stat_v2(stream: &mut impl Read) -> AGResult<(u32, FileStat)> {
+    let error = read_u32(stream)?;
+    let _dev = read_u64(stream)?;
+    let _ino = read_u64(stream)?;
+    let mode = read_u32(stream)?;
+    let _nlink = read_u32(stream)?;
+    let _uid = read_u32(stream)?;
+    let _gid = read_u32(stream)?;
+    let size = read_u64(stream)?;
+    let _atime = read_u64(stream)?;
+    let mtime = read_u64(stream)? as i64;
+    let _ctime = read_u64(stream)?;
+    Ok((error, FileStat { mode, size, mtime }))
+}
+
+fn read_name(stream: &mut impl Read) -> AGResult<String> {
+    let length = read_u32(stream)? as usize;
+    let mut name = vec![0u8; length];
+    stream.read_exact(&mut name)?;
+    Ok(String::from_utf8_lossy(&name).to_string())
+}
+
+impl ADB {
+    fn sync_start(&mut self) -> AGResult<()> {
+        self.transport()?;
+        self.open_service("sync:")
+    }
+
+    fn sync_finish<T>(&mut self, result: AGResult<T>) -> AGResult<T> {
+        if result.is_ok() {
+            write_request(&mut self.stream, b"QUIT", &[])?;
+        }
+        self.reset()?;
+        result.map_err(|e| e.in_service(&self.target, "sync:"))
+    }
+
+    /// Streams `reader` to `remote` on the device, creating it with permission bits `mode` and modification time `mtime`.
+    pub fn push_from(&mut self, reader: &mut impl Read, remote: &str, mode: u32, mtime: u32) -> AGResult<()> {
+        self.sync_start()?;
+        let result = self.send_file(reader, remote, mode, mtime);
+        self.sync_finish(result)
+    }
+
+    fn send_file(&mut self, reader: &mut impl Read, remote: &str, mode: u32, mtime: u32) -> AGResult<()> {
+        write_request(&mut self.stream, b"SEND", format!("{},{}", remote, mode).as_bytes())?;
+        let mut chunk = vec![0u8; MAX_CHUNK];
+        loop {
+            let n = reader.read(&mut chunk)?;
+            if n == 0 {
+                break;
+            }
+            write_request(&mut self.stream, b"DATA", &chunk[..n])?;
+        }
+        self.stream.write_all(b"DONE")?;
+        self.stream.write_all(&mtime.to_le_bytes())?;
+        match &read_id(&mut self.stream)? {
+            b"OKAY" => {
+                read_u32(&mut self.stream)?;
+                Ok(())
+            }
+            b"FAIL" => Err(read_fail(&mut self.stream, &self.target)?),
+            id => Err(unexpected(*id)),
+        }
+    }
+
+    /// Copies the local file at `local` to `remote` on the device.
+    pub fn push(&mut self, local: impl AsRef<Path>, remote: &str, mode: u32) -> AGResult<()> {
+        let mut file = File::open(local)?;
+        let mtime = file
+            .metadata()?
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as u32)
+            .unwrap_or(0);
+        self.push_from(&mut file, remote, mode, mtime)
+    }
+
+    /// Streams `remote` from the device into `writer`, returning the number of bytes copied.
+    pub fn pull_into(&mut self, remote: &str, writer: &mut impl Write) -> AGResult<u64> {
+        self.sync_start()?;
+        let result = self.recv_file(remote, writer);
+        self.sync_finish(result)
+    }
+
+    fn recv_file(&mut self, remote: &str, writer: &mut impl Write) -> AGResult<u64> {
+        write_request(&mut self.stream, b"RECV", remote.as_bytes())?;
+        let mut total = 0;
+        loop {
+            match &read_id(&mut self.stream)? {
+                b"DATA" => {
+                    let length = read_u32(&mut self.stream)? as u64;
+                    let copied = std::io::copy(&mut (&mut self.stream).take(length), writer)?;
+                    if copied != length {
+                        return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+                    }
+                    total += copied;
+                }
+                b"DONE" => {
+                    read_u32(&mut self.stream)?;
+                    return Ok(total);
+                }
+                b"FAIL" => return Err(read_fail(&mut self.stream, &self.target)?),
+                id => return Err(unexpected(*id)),
+            }
+        }
+    }
+
+    /// Copies `remote` from the device to the local file at `local`. The data goes to `<local>.part` first, so a failed
+    /// pull leaves an existing `local` untouched.
+    pub fn pull(&mut self, remote: &str, local: impl AsRef<Path>) -> AGResult<u64> {
+        let local = local.as_ref();
+        let mut part = local.as_os_str().to_owned();
+        part.push(".part");
+        let part = PathBuf::from(part);
+        let result = File::create(&part)
+            .map_err(AGError::from)
+            .and_then(|mut file| self.pull_into(remote, &mut file))
+            .and_then(|size| Ok(std::fs::rename(&part, local).map(|_| size)?));
+        if result.is_err() {
+            let _ = std::fs::remove_file(&part);
+        }
+        result
+    }
+
+    /// Stats `remote`, using `STA2` when the device advertises `stat_v2`.
+    pub fn stat(&mut self, remote: &str) -> AGResult<FileStat> {
+        let v2 = self.has_feature("stat_v2")?;
+        self.sync_start()?;
+        let result = self.stat_file(remote, v2);
+        self.sync_finish(result)
+    }
+
+    fn stat_file(&mut self, remote: &str, v2: bool) -> AGResult<FileStat> {
+        let not_found = || std::io::Error::new(std::io::ErrorKind::NotFound, remote.to_string()).into();
+        if v2 {
+            write_request(&mut self.stream, b"STA2", remote.as_bytes())?;
+            match &read_id(&mut self.stream)? {
+                b"STA2" => {
+                    let (error, stat) = read_stat_v2(&mut self.stream)?;
+                    if error != 0 {
+                        return Err(not_found());
+                    }
+                    Ok(stat)
+                }
+                b"FAIL" => Err(read_fail(&mut self.stream, &self.target)?),
+                id => Err(unexpected(*id)),
+            }
+        } else {
+            write_request(&mut self.stream, b"STAT", remote.as_bytes())?;
+            match &read_id(&mut self.stream)? {
+                b"STAT" => {
+                    let mode = read_u32(&mut self.stream)?;
+                    let size = read_u32(&mut self.stream)? as u64;
+                    let mtime = read_u32(&mut self.stream)? as i64;
+                    if mode == 0 && size == 0 && mtime == 0 {
+                        return Err(not_found());
+                    }
+                    Ok(FileStat { mode, size, mtime })
+                }
+                b"FAIL" => Err(read_fail(&mut self.stream, &self.target)?),
+                id => Err(unexpected(*id)),
+            }
+        }
+    }
+
+    /// Lists the entries of the directory `remote`, without `.` and `..`. Uses `LIS2` when the device advertises `ls_v2`.
+    pub fn list_dir(&mut self, remote: &str) -> AGResult<Vec<DirEntry>> {
+        let v2 = self.has_feature("ls_v2")?;
+        self.sync_start()?;
+        let result = self.list_entries(remote, v2);
+        self.sync_finish(result)
+    }
+
+    fn list_entries(&mut self, remote: &str, v2: bool) -> AGResult<Vec<DirEntry>> {
+        write_request(&mut self.stream, if v2 { b"LIS2" } else { b"LIST" }, remote.as_bytes())?;
+        let mut entries = Vec::new();
+        loop {
+            let id = read_id(&mut self.stream)?;
+            let entry = match (&id, v2) {
+                (b"DENT", false) => {
+                    let mode = read_u32(&mut self.stream)?;
+                    let size = read_u32(&mut self.stream)? as u64;
+                    let mtime = read_u32(&mut self.stream)? as i64;
+                    let name = read_name(&mut self.stream)?;
+                    DirEntry {
+                        name,
+                        stat: FileStat { mode, size, mtime },
+                    }
+                }
+                (b"DNT2", true) => {
+                    let (_, stat) = read_stat_v2(&mut self.stream)?;
+                    let name = read_name(&mut self.stream)?;
+                    DirEntry { name, stat }
+                }
+                (b"DONE", false) => {
+                    let mut rest = [0u8; 16];
+                    self.stream.read_exact(&mut rest)?;
+                    return Ok(entries);
+                }
+                (b"DONE", true) => {
+                    read_stat_v2(&mut self.stream)?;
+                    read_u32(&mut self.stream)?;
+                    return Ok(entries);
+                }
+                (b"FAIL", _) => return Err(read_fail(&mut self.stream, &self.target)?),
+                _ => return Err(unexpected(id)),
+            };
+            if entry.name != "." && entry.name != ".." {
+                entries.push(entry);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock::MockAdbServer;
+    use super::*;
+    use crate::controller::AdbBuilder;
+
+    fn build(server: &MockAdbServer) -> ADB {
+        AdbBuilder::new().with_addr(server.addr()).with_target("emulator-5554").build().unwrap()
+    }
+
+    #[test]
+    fn push_then_pull_roundtrip() {
+        let server = MockAdbServer::start();
+        let mut adb = build(&server);
+        let data: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        adb.push_from(&mut data.as_slice(), "/data/local/tmp/blob", 0o755, 42).unwrap();
+        let file = server.file("/data/local/tmp/blob").unwrap();
+        assert_eq!(file.data, data);
+        assert_eq!(file.mode, 0o100755);
+        assert_eq!(file.mtime, 42);
+        let mut pulled = Vec::new();
+        assert_eq!(adb.pull_into("/data/local/tmp/blob", &mut pulled).unwrap(), data.len() as u64);
+        assert_eq!(pulled, data);
+
+        let local = std::env::temp_dir().join(format!("autogui-roundtrip-{}", std::process::id()));
+        assert_eq!(adb.pull("/data/local/tmp/blob", &local).unwrap(), data.len() as u64);
+        assert_eq!(std::fs::read(&local).unwrap(), data);
+        std::fs::remove_file(local).unwrap();
+    }
+
+    #[test]
+    fn pull_missing_file_fails() {
+        let server = MockAdbServer::start();
+        let mut adb = build(&server);
+        assert!(adb.pull_into("/nope", &mut Vec::new()).is_err());
+        assert!(adb.shell("true").is_ok());
+
+        let local = std::env::temp_dir().join(format!("autogui-pull-{}", std::process::id()));
+        std::fs::write(&local, "keep").unwrap();
+        assert!(adb.pull("/nope", &local).is_err());
+        assert_eq!(std::fs::read_to_string(&local).unwrap(), "keep");
+        let mut part = local.clone().into_os_string();
+        part.push(".part");
+        assert!(!Path::new(&part).exists());
+        std::fs::remove_file(local).unwrap();
+    }
+
+    #[test]
+    fn stat_and_list_v1() {
+        let server = MockAdbServer::start()
+            .with_file("/sdcard/a.png", vec![0u8; 10])
+            .with_file("/sdcard/sub/b.png", "b");
+        let mut adb = build(&server);
+        let stat = adb.stat("/sdcard/a.png").unwrap();
+        assert!(stat.is_file());
+        assert_eq!(stat.size, 10);
+        assert!(adb.stat("/sdcard").unwrap().is_dir());
+        assert!(adb.stat("/missing").is_err());
+        let entries = adb.list_dir("/sdcard").unwrap();
+        let names: Vec<_> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["a.png", "sub"]);
+        assert!(entries[1].stat.is_dir());
+        assert!(server.requests().iter().all(|r| !r.contains("STA2")));
+    }
+
+    #[test]
+    fn stat_and_list_v2() {
+        let server = MockAdbServer::start()
+            .with_features(&["shell_v2", "stat_v2", "ls_v2"])
+            .with_file("/sdcard/a.png", vec![0u8; 10]);
+        let mut adb = build(&server);
+        let stat = adb.stat("/sdcard/a.png").unwrap();
+        assert_eq!(
+            stat,
+            FileStat {
+                mode: 0o100644,
+                size: 10,
+                mtime: 1_700_000_000
+            }
+        );
+        assert!(adb.stat("/missing").is_err());
+        let entries = adb.list_dir("/sdcard/").unwrap();
+        assert_eq!(
+            entries,
+            [DirEntry {
+                name: "a.png".to_string(),
+                stat
+            }]
+        );
+        let features = server.requests().iter().filter(|r| r.ends_with(":features")).count();
+        assert_eq!(features, 1);
+    }
+}
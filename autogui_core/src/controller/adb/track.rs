@@ -0,0 +1,220 @@
+//! Device hot-plug notifications via `host:track-devices`.
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::{Shutdown, TcpStream};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::{AGError, AGResult};
+
+use super::codec::{self, Status};
+use super::{DeviceInfo, DeviceState, ADB};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceEvent {
+    Added(DeviceInfo),
+    Removed(DeviceInfo),
+    StateChanged { serial: String, old: DeviceState, new: DeviceState },
+}
+
+impl DeviceEvent {
+    pub fn serial(&self) -> &str {
+        match self {
+            DeviceEvent::Added(info) | DeviceEvent::Removed(info) => &info.serial,
+            DeviceEvent::StateChanged { serial, .. } => serial,
+        }
+    }
+
+    /// Events turning the `old` device list into `new`.
+    pub fn diff(old: &[DeviceInfo], new: &[DeviceInfo]) -> Vec<DeviceEvent> {
+        let mut events = Vec::new();
+        for info in old {
+            if !new.iter().any(|d| d.serial == info.serial) {
+                events.push(DeviceEvent::Removed(info.clone()));
+            }
+        }
+        for info in new {
+            match old.iter().find(|d| d.serial == info.serial) {
+                None => events.push(DeviceEvent::Added(info.clone())),
+                Some(prev) if prev.state != info.state => events.push(DeviceEvent::StateChanged {
+                    serial: info.serial.clone(),
+                    old: prev.state.clone(),
+                    new: info.state.clone(),
+                }),
+                Some(_) => {}
+            }
+        }
+        events
+    }
+}
+
+/// Holds a `host:track-devices` connection open and yields a `DeviceEvent` whenever the device list changes.
+/// The first snapshot is reported as one `Added` per attached device. Dropping the tracker closes the connection.
+pub struct DeviceTracker {
+    stream: TcpStream,
+    events: Receiver<DeviceEvent>,
+    devices: HashMap<String, DeviceState>,
+}
+
+fn read_snapshot(stream: &mut TcpStream) -> AGResult<Vec<DeviceInfo>> {
+    Ok(DeviceInfo::parse_list(&String::from_utf8_lossy(&codec::read_message(stream)?)))
+}
+
+impl DeviceTracker {
+    pub fn new(addr: impl std::net::ToSocketAddrs) -> AGResult<Self> {
+        let mut stream = TcpStream::connect(addr)?;
+        stream.write_all(&codec::encode_message(b"host:track-devices")?)?;
+        if codec::read_status(&mut stream)? == Status::Fail {
+            let message = codec::read_message(&mut stream)
+                .map(|m| String::from_utf8_lossy(&m).to_string())
+                .unwrap_or_default();
+            return Err(AGError::AdbFail {
+                serial: String::new(),
+                service: "host:track-devices".to_string(),
+                message,
+            });
+        }
+        let (tx, events) = mpsc::channel();
+        let mut reader = stream.try_clone()?;
+        thread::spawn(move || {
+            let mut devices = Vec::new();
+            while let Ok(snapshot) = read_snapshot(&mut reader) {
+                for event in DeviceEvent::diff(&devices, &snapshot) {
+                    if tx.send(event).is_err() {
+                        return;
+                    }
+                }
+                devices = snapshot;
+            }
+        });
+        Ok(Self {
+            stream,
+            events,
+            devices: HashMap::new(),
+        })
+    }
+
+    fn apply(&mut self, event: &DeviceEvent) {
+        match event {
+            DeviceEvent::Added(info) => {
+                self.devices.insert(info.serial.clone(), info.state.clone());
+            }
+            DeviceEvent::Removed(info) => {
+                self.devices.remove(&info.serial);
+            }
+            DeviceEvent::StateChanged { serial, new, .. } => {
+                self.devices.insert(serial.clone(), new.clone());
+            }
+        }
+    }
+
+    /// Waits up to `timeout` for the next event. Returns `None` on timeout or once the connection is gone.
+    pub fn next_timeout(&mut self, timeout: Duration) -> Option<DeviceEvent> {
+        let event = self.events.recv_timeout(timeout).ok()?;
+        self.apply(&event);
+        Some(event)
+    }
+
+    /// State of `serial` according to the events consumed so far.
+    pub fn state(&self, serial: &str) -> Option<&DeviceState> {
+        self.devices.get(serial)
+    }
+
+    /// Blocks until `serial` is in `state`, e.g. to pause a bot until a restarted emulator is back online.
+    pub fn wait_for(&mut self, serial: &str, state: DeviceState, timeout: Duration) -> AGResult<()> {
+        let deadline = Instant::now() + timeout;
+        while self.state(serial) != Some(&state) {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.events.recv_timeout(remaining) {
+                Ok(event) => self.apply(&event),
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(AGError::Timeout {
+                        serial: serial.to_string(),
+                        service: "host:track-devices".to_string(),
+                    })
+                }
+                Err(RecvTimeoutError::Disconnected) => return Err(AGError::Custom("track-devices connection closed".to_string())),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for DeviceTracker {
+    type Item = DeviceEvent;
+
+    fn next(&mut self) -> Option<DeviceEvent> {
+        let event = self.events.recv().ok()?;
+        self.apply(&event);
+        Some(event)
+    }
+}
+
+impl Drop for DeviceTracker {
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+impl ADB {
+    /// Opens a `DeviceTracker` on the same ADB server, on a connection of its own.
+    pub fn track_devices(&self) -> AGResult<DeviceTracker> {
+        DeviceTracker::new(self.stream.peer_addr()?)
+    }
+
+    /// Blocks until this `ADB`'s target is in the `device` state, e.g. after an emulator restart.
+    pub fn wait_for_device(&self, timeout: Duration) -> AGResult<()> {
+        self.track_devices()?.wait_for(&self.target, DeviceState::Device, timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock::MockAdbServer;
+    use super::*;
+    use crate::controller::AdbBuilder;
+
+    #[test]
+    fn diff_reports_changes() {
+        let old = DeviceInfo::parse_list("a\tdevice\nb\tdevice\n");
+        let new = DeviceInfo::parse_list("a\toffline\nc\tdevice\n");
+        let events = DeviceEvent::diff(&old, &new);
+        assert_eq!(events.len(), 3);
+        assert!(matches!(&events[0], DeviceEvent::Removed(info) if info.serial == "b"));
+        assert_eq!(
+            events[1],
+            DeviceEvent::StateChanged {
+                serial: "a".to_string(),
+                old: DeviceState::Device,
+                new: DeviceState::Offline
+            }
+        );
+        assert!(matches!(&events[2], DeviceEvent::Added(info) if info.serial == "c"));
+    }
+
+    #[test]
+    fn tracker_follows_mock_server() {
+        let server = MockAdbServer::start().with_device("emulator-5554");
+        let adb = AdbBuilder::new().with_addr(server.addr()).build().unwrap();
+        let mut tracker = adb.track_devices().unwrap();
+        let timeout = Duration::from_secs(2);
+        assert!(matches!(tracker.next_timeout(timeout), Some(DeviceEvent::Added(info)) if info.serial == "emulator-5554"));
+        server.set_device_state("emulator-5554", Some("offline"));
+        assert_eq!(
+            tracker.next_timeout(timeout),
+            Some(DeviceEvent::StateChanged {
+                serial: "emulator-5554".to_string(),
+                old: DeviceState::Device,
+                new: DeviceState::Offline
+            })
+        );
+        server.set_device_state("emulator-5554", Some("device"));
+        tracker.wait_for("emulator-5554", DeviceState::Device, timeout).unwrap();
+        server.set_device_state("emulator-5554", None);
+        assert!(matches!(tracker.next_timeout(timeout), Some(DeviceEvent::Removed(info)) if info.serial == "emulator-5554"));
+        assert!(tracker
+            .wait_for("emulator-5554", DeviceState::Device, Duration::from_millis(100))
+            .is_err());
+    }
+}
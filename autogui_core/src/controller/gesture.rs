@@ -0,0 +1,95 @@
+//! Multi-finger touch gestures: one path per finger, all played back together over the same duration.
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gesture {
+    /// Time from the first touch to the last move; a single-point gesture holds its touch this long.
+    pub duration: Duration,
+    /// Screen coordinates for each finger, visited at even intervals. A curve is a polyline with enough points.
+    /// Only added through `with_path`, which keeps every path non-empty.
+    paths: Vec<Vec<(u32, u32)>>,
+}
+
+impl Gesture {
+    pub fn new(duration: Duration) -> Self {
+        Gesture { duration, paths: Vec::new() }
+    }
+
+    /// Adds a finger following `points`. Empty paths are ignored.
+    pub fn with_path(mut self, points: Vec<(u32, u32)>) -> Self {
+        if !points.is_empty() {
+            self.paths.push(points);
+        }
+        self
+    }
+
+    pub fn paths(&self) -> &[Vec<(u32, u32)>] {
+        &self.paths
+    }
+
+    pub fn tap(x: u32, y: u32) -> Self {
+        Gesture::new(Duration::ZERO).with_path(vec![(x, y)])
+    }
+
+    /// Two fingers on a horizontal line through `center`, moving from `from` to `to` pixels apart: a zoom-in when
+    /// `to > from`, a pinch when `to < from`.
+    pub fn pinch(center: (u32, u32), from: u32, to: u32, duration: Duration) -> Self {
+        let (x, y) = center;
+        let (from, to) = (from / 2, to / 2);
+        Gesture::new(duration)
+            .with_path(vec![(x.saturating_sub(from), y), (x.saturating_sub(to), y)])
+            .with_path(vec![(x.saturating_add(from), y), (x.saturating_add(to), y)])
+    }
+
+    /// Number of time steps, the length of the longest path.
+    pub fn frames(&self) -> usize {
+        self.paths.iter().map(|p| p.len()).max().unwrap_or(0)
+    }
+
+    /// Time between two frames.
+    pub fn step(&self) -> Duration {
+        match self.frames() {
+            0 | 1 => self.duration,
+            frames => self.duration / (frames as u32 - 1),
+        }
+    }
+
+    /// Where `finger` is at `frame`, interpolated linearly when its path is shorter than the gesture. Panics if
+    /// `finger` is not below `paths().len()`.
+    pub fn position(&self, finger: usize, frame: usize) -> (u32, u32) {
+        let path = &self.paths[finger];
+        let frames = self.frames();
+        if path.len() == 1 || frames < 2 {
+            return path[0];
+        }
+        let pos = frame.min(frames - 1) as f64 * (path.len() - 1) as f64 / (frames - 1) as f64;
+        let (i, t) = (pos.floor() as usize, pos.fract());
+        let (a, b) = (path[i], path[(i + 1).min(path.len() - 1)]);
+        let lerp = |a: u32, b: u32| (a as f64 + (b as f64 - a as f64) * t).round() as u32;
+        (lerp(a.0, b.0), lerp(a.1, b.1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_are_resampled() {
+        let gesture = Gesture::new(Duration::from_millis(300))
+            .with_path(vec![(0, 0), (10, 10), (20, 0), (30, 10)])
+            .with_path(vec![(100, 100), (100, 130)])
+            .with_path(Vec::new());
+        assert_eq!(gesture.frames(), 4);
+        assert_eq!(gesture.step(), Duration::from_millis(100));
+        assert_eq!(gesture.position(0, 2), (20, 0));
+        assert_eq!(gesture.position(1, 1), (100, 110));
+        assert_eq!(gesture.position(1, 3), (100, 130));
+
+        let pinch = Gesture::pinch((500, 400), 400, 100, Duration::from_millis(200));
+        assert_eq!(pinch.paths, [vec![(300, 400), (450, 400)], vec![(700, 400), (550, 400)]]);
+        assert_eq!(Gesture::tap(1, 2).step(), Duration::ZERO);
+        let wide = Gesture::pinch((u32::MAX - 10, 0), 0, 100, Duration::ZERO);
+        assert_eq!(wide.paths()[1], [(u32::MAX - 10, 0), (u32::MAX, 0)]);
+    }
+}
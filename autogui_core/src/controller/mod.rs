@@ -1,12 +1,34 @@
 mod adb;
+mod gesture;
+mod rect;
 use crate::error::{AGError, AGResult};
-pub use adb::{AdbBuilder, ADB};
+pub use adb::{
+    codec, parse_getprop, Activity, AdbBuilder, AdbKey, Candidate, Channel, Connection, ControlMessage, CopyKey, DeviceEvent, DeviceInfo,
+    DeviceMessage, DeviceProfile, DeviceSelector, DeviceState, DeviceTracker, DirEntry, Discovery, DisplayInfo, Emulator, FileStat, Forward,
+    ForwardSpec, FramebufferHeader, InstallOptions, KeyAction, LogEntry, LogLevel, Logcat, LogcatFilter, Minicap, MinicapBanner, Minitouch,
+    MinitouchBanner, PackageVersion, PixelFormat, PortRange, Position, RawHeader, ReconnectPolicy, ScrcpyControl, ScreenPowerMode, ScreenshotMode,
+    Selector, ShellOutput, ShellStream, TouchAction, UiHierarchy, UiNode, ADB,
+};
+pub use gesture::Gesture;
 use image::RgbaImage;
+pub use rect::Rect;
+use std::time::Duration;
 
 pub trait Controller {
     fn screenshot(&mut self) -> AGResult<RgbaImage>;
     fn click(&mut self, x: u32, y: u32) -> AGResult<()>;
-    fn swipe<'a>(&mut self, x1: u32, y1: u32, x2: u32, y2: u32) -> AGResult<()>;
+    fn swipe(&mut self, x1: u32, y1: u32, x2: u32, y2: u32) -> AGResult<()>;
+    /// Swipes from `(x1, y1)` to `(x2, y2)` over `duration`; short durations fling, long ones scroll precisely.
+    fn swipe_with_duration(&mut self, x1: u32, y1: u32, x2: u32, y2: u32, duration: Duration) -> AGResult<()>;
+    /// Touches `(x, y)` for `duration` without moving.
+    fn long_press(&mut self, x: u32, y: u32, duration: Duration) -> AGResult<()> {
+        self.swipe_with_duration(x, y, x, y, duration)
+    }
+    /// Touches `(x1, y1)` for `hold`, so that the touched item gets picked up, then moves to `(x2, y2)` over `duration`
+    /// and releases.
+    fn drag(&mut self, x1: u32, y1: u32, x2: u32, y2: u32, hold: Duration, duration: Duration) -> AGResult<()>;
+    /// Plays all paths of `gesture` at once; backends without multi-touch reject gestures with several paths.
+    fn gesture(&mut self, gesture: &Gesture) -> AGResult<()>;
     fn press_key(&mut self, keycode: u32) -> AGResult<()>;
     fn get_resolution(&mut self) -> AGResult<(u32, u32)>;
     fn input_text(&mut self, text: &str) -> AGResult<()>;
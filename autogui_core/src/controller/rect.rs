@@ -0,0 +1,35 @@
+/// An axis-aligned screen rectangle, `right` and `bottom` exclusive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Rect {
+    pub left: u32,
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+}
+
+impl Rect {
+    pub fn new(left: u32, top: u32, right: u32, bottom: u32) -> Self {
+        Rect { left, top, right, bottom }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.right.saturating_sub(self.left)
+    }
+
+    pub fn height(&self) -> u32 {
+        self.bottom.saturating_sub(self.top)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width() == 0 || self.height() == 0
+    }
+
+    /// The point to click.
+    pub fn center(&self) -> (u32, u32) {
+        (self.left + self.width() / 2, self.top + self.height() / 2)
+    }
+
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        (self.left..self.right).contains(&x) && (self.top..self.bottom).contains(&y)
+    }
+}
@@ -9,6 +9,67 @@ pub enum AGError {
     Decode,
     #[error("Image Error:{0}")]
     Image(#[from] image::error::ImageError),
+    #[error("command `{cmd}` exited with {exit_code}: {stderr}")]
+    ShellExit { cmd: String, exit_code: i32, stderr: String },
+    #[error("package manager failed with {code}: {message}")]
+    Package { code: String, message: String },
+    #[error("`{name}` is not a valid {kind} name")]
+    InvalidName { kind: String, name: String },
+    #[error("cannot start {target}: {message}")]
+    StartFailed { target: String, message: String },
+    #[error("adb refused `{service}` for {serial}: {message}")]
+    AdbFail { serial: String, service: String, message: String },
+    #[error("device {serial} not found")]
+    DeviceNotFound { serial: String },
+    #[error("no ready device{}", .model.as_ref().map(|m| format!(" with model '{}'", m)).unwrap_or_default())]
+    NoDevice { model: Option<String> },
+    #[error("more than one device/emulator: {}", .serials.join(", "))]
+    MultipleDevices { serials: Vec<String> },
+    #[error("device {serial} has no valid `{name}` property")]
+    MissingProperty { serial: String, name: String },
+    #[error("device {serial} is offline")]
+    DeviceOffline { serial: String },
+    #[error("device {serial} is unauthorized")]
+    DeviceUnauthorized { serial: String },
+    #[error("timed out on `{service}` for {serial}")]
+    Timeout { serial: String, service: String },
+    #[error("connection to {serial} dropped during {operation}; reconnected, but not retried")]
+    Interrupted { serial: String, operation: String },
+    #[error("reconnected to {serial}, but lost {}; set them up again", .lost.join(", "))]
+    SessionsLost { serial: String, lost: Vec<String> },
+    #[error("payload of {0} bytes does not fit a 4-hex-digit length prefix")]
+    PayloadTooLarge(usize),
     #[error("Custom Error:{0}")]
     Custom(String),
 }
+
+impl AGError {
+    /// Classifies the message of a `FAIL` reply to `service`.
+    pub(crate) fn adb_fail(serial: &str, service: &str, message: String) -> Self {
+        let serial = serial.to_string();
+        if (message.starts_with("device") && message.contains("not found")) || message.contains("no devices") {
+            AGError::DeviceNotFound { serial }
+        } else if message.contains("offline") {
+            AGError::DeviceOffline { serial }
+        } else if message.contains("unauthorized") {
+            AGError::DeviceUnauthorized { serial }
+        } else {
+            AGError::AdbFail {
+                serial,
+                service: service.to_string(),
+                message,
+            }
+        }
+    }
+
+    /// Turns a socket read or write timeout into `Timeout` for the service that was waiting.
+    pub(crate) fn in_service(self, serial: &str, service: &str) -> Self {
+        match self {
+            AGError::Io(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => AGError::Timeout {
+                serial: serial.to_string(),
+                service: service.to_string(),
+            },
+            other => other,
+        }
+    }
+}
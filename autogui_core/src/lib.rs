@@ -1,11 +1,19 @@
 mod controller;
 mod error;
-pub use controller::{AdbBuilder, Controller, ADB};
+pub use controller::{
+    codec, parse_getprop, Activity, AdbBuilder, AdbKey, Candidate, Channel, Connection, ControlMessage, Controller, CopyKey, DeviceEvent, DeviceInfo,
+    DeviceMessage, DeviceProfile, DeviceSelector, DeviceState, DeviceTracker, DirEntry, Discovery, DisplayInfo, Emulator, FileStat, Forward,
+    ForwardSpec, FramebufferHeader, Gesture, InstallOptions, KeyAction, LogEntry, LogLevel, Logcat, LogcatFilter, Minicap, MinicapBanner, Minitouch,
+    MinitouchBanner, PackageVersion, PixelFormat, PortRange, Position, RawHeader, ReconnectPolicy, Rect, ScrcpyControl, ScreenPowerMode,
+    ScreenshotMode, Selector, ShellOutput, ShellStream, TouchAction, UiHierarchy, UiNode, ADB,
+};
+pub use error::{AGError, AGResult};
 #[cfg(test)]
 mod tests {
     use crate::controller::AdbBuilder;
 
     #[test]
+    #[ignore = "needs an emulator at 127.0.0.1:7555"]
     fn it_works() -> Result<(), Box<dyn std::error::Error>> {
         let mut adb = AdbBuilder::new()
             .with_target("127.0.0.1:7555")
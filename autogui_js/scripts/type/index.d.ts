@@ -13,7 +13,48 @@ class Adb {
     protected target: string
     constructor(addr: string, target: string, bin_path: string)
     click(x: number, y: number): void
+    swipe(x1: number, y1: number, x2: number, y2: number, ms?: number): void
+    long_press(x: number, y: number, ms: number): void
+    drag(x1: number, y1: number, x2: number, y2: number, hold_ms: number, ms: number): void
     screenshot(): Image
+    start_app(pkg: string, activity?: string): void
+    stop_app(pkg: string): void
+    foreground(): Activity | null
+    find_element(path: string): Rect | null
+    wait_for_log(text: string, timeout_ms: number, tag?: string): LogEntry | null
+    device_profile(): DeviceProfile
+}
+
+interface DeviceProfile {
+    sdk: number
+    release: string
+    abis: string[]
+    manufacturer: string
+    model: string
+    density: number
+    width: number
+    height: number
+    emulator: "MuMu" | "LDPlayer" | "BlueStacks" | "Nox" | null
+}
+
+interface LogEntry {
+    pid: number
+    tid: number
+    level: string
+    tag: string
+    message: string
+}
+
+interface Rect {
+    left: number
+    top: number
+    right: number
+    bottom: number
+}
+
+interface Activity {
+    package: string
+    activity: string
 }
 
 class Point {
@@ -1,10 +1,13 @@
 use autogui_core::Controller;
 use boa_engine::{
     class::{Class, ClassBuilder},
-    object::{ObjectData, PROTOTYPE},
-    Context, JsArgs, JsNativeError, JsObject, JsResult, JsValue, NativeFunction,
+    js_string,
+    object::{builtins::JsArray, ObjectData, ObjectInitializer, PROTOTYPE},
+    property::Attribute,
+    Context, JsArgs, JsNativeError, JsObject, JsResult, JsString, JsValue, NativeFunction,
 };
 use boa_gc::Finalize;
+use std::time::Duration;
 
 use crate::js_image::JsImage;
 
@@ -27,6 +30,59 @@ impl JsAdb {
         Err(JsNativeError::typ().with_message("'this' is not a Adb object").into())
     }
 
+    /// `swipe(x1, y1, x2, y2, ms?)`
+    pub fn swipe(this: &JsValue, args: &[JsValue], context: &mut Context<'_>) -> JsResult<JsValue> {
+        if let Some(object) = this.as_object() {
+            if let Some(mut adb) = object.downcast_mut::<Self>() {
+                let x1 = args.get_or_undefined(0).to_u32(context)?;
+                let y1 = args.get_or_undefined(1).to_u32(context)?;
+                let x2 = args.get_or_undefined(2).to_u32(context)?;
+                let y2 = args.get_or_undefined(3).to_u32(context)?;
+                let result = match args.get(4).filter(|a| !a.is_undefined()) {
+                    Some(ms) => adb.0.swipe_with_duration(x1, y1, x2, y2, Duration::from_millis(ms.to_u32(context)? as u64)),
+                    None => adb.0.swipe(x1, y1, x2, y2),
+                };
+                result.map_err(|e| JsNativeError::typ().with_message(e.to_string()))?;
+                return Ok(JsValue::undefined());
+            }
+        }
+        Err(JsNativeError::typ().with_message("'this' is not a Adb object").into())
+    }
+
+    pub fn long_press(this: &JsValue, args: &[JsValue], context: &mut Context<'_>) -> JsResult<JsValue> {
+        if let Some(object) = this.as_object() {
+            if let Some(mut adb) = object.downcast_mut::<Self>() {
+                let x = args.get_or_undefined(0).to_u32(context)?;
+                let y = args.get_or_undefined(1).to_u32(context)?;
+                let ms = args.get_or_undefined(2).to_u32(context)?;
+                adb.0
+                    .long_press(x, y, Duration::from_millis(ms as u64))
+                    .map_err(|e| JsNativeError::typ().with_message(e.to_string()))?;
+                return Ok(JsValue::undefined());
+            }
+        }
+        Err(JsNativeError::typ().with_message("'this' is not a Adb object").into())
+    }
+
+    /// `drag(x1, y1, x2, y2, hold_ms, ms)`
+    pub fn drag(this: &JsValue, args: &[JsValue], context: &mut Context<'_>) -> JsResult<JsValue> {
+        if let Some(object) = this.as_object() {
+            if let Some(mut adb) = object.downcast_mut::<Self>() {
+                let x1 = args.get_or_undefined(0).to_u32(context)?;
+                let y1 = args.get_or_undefined(1).to_u32(context)?;
+                let x2 = args.get_or_undefined(2).to_u32(context)?;
+                let y2 = args.get_or_undefined(3).to_u32(context)?;
+                let hold = Duration::from_millis(args.get_or_undefined(4).to_u32(context)? as u64);
+                let duration = Duration::from_millis(args.get_or_undefined(5).to_u32(context)? as u64);
+                adb.0
+                    .drag(x1, y1, x2, y2, hold, duration)
+                    .map_err(|e| JsNativeError::typ().with_message(e.to_string()))?;
+                return Ok(JsValue::undefined());
+            }
+        }
+        Err(JsNativeError::typ().with_message("'this' is not a Adb object").into())
+    }
+
     pub fn screenshot(this: &JsValue, _args: &[JsValue], context: &mut Context<'_>) -> JsResult<JsValue> {
         if let Some(object) = this.as_object() {
             if let Some(mut adb) = object.downcast_mut::<Self>() {
@@ -47,6 +103,135 @@ impl JsAdb {
         }
         Err(JsNativeError::typ().with_message("'this' is not a Adb object").into())
     }
+
+    /// `start_app(package)`, or `start_app(package, activity)` to start a specific activity.
+    pub fn start_app(this: &JsValue, args: &[JsValue], context: &mut Context<'_>) -> JsResult<JsValue> {
+        if let Some(object) = this.as_object() {
+            if let Some(mut adb) = object.downcast_mut::<Self>() {
+                let package = args.get_or_undefined(0).to_string(context)?.to_std_string_escaped();
+                let result = match args.get(1).filter(|a| !a.is_undefined()) {
+                    Some(activity) => {
+                        let activity = activity.to_string(context)?.to_std_string_escaped();
+                        let activity = autogui_core::Activity::parse(&format!("{}/{}", package, activity))
+                            .ok_or_else(|| JsNativeError::typ().with_message("invalid activity name"))?;
+                        adb.0.start_activity(&activity)
+                    }
+                    None => adb.0.start_app(&package),
+                };
+                result.map_err(|e| JsNativeError::typ().with_message(e.to_string()))?;
+                return Ok(JsValue::undefined());
+            }
+        }
+        Err(JsNativeError::typ().with_message("'this' is not a Adb object").into())
+    }
+
+    pub fn stop_app(this: &JsValue, args: &[JsValue], context: &mut Context<'_>) -> JsResult<JsValue> {
+        if let Some(object) = this.as_object() {
+            if let Some(mut adb) = object.downcast_mut::<Self>() {
+                let package = args.get_or_undefined(0).to_string(context)?.to_std_string_escaped();
+                adb.0.force_stop(&package).map_err(|e| JsNativeError::typ().with_message(e.to_string()))?;
+                return Ok(JsValue::undefined());
+            }
+        }
+        Err(JsNativeError::typ().with_message("'this' is not a Adb object").into())
+    }
+
+    /// `{ package, activity }` of the foreground activity, or `null`.
+    pub fn foreground(this: &JsValue, _args: &[JsValue], context: &mut Context<'_>) -> JsResult<JsValue> {
+        if let Some(object) = this.as_object() {
+            if let Some(mut adb) = object.downcast_mut::<Self>() {
+                let activity = adb.0.foreground().map_err(|e| JsNativeError::typ().with_message(e.to_string()))?;
+                let Some(activity) = activity else {
+                    return Ok(JsValue::null());
+                };
+                let object = ObjectInitializer::new(context)
+                    .property(js_string!("package"), JsString::from(activity.package.as_str()), Attribute::all())
+                    .property(js_string!("activity"), JsString::from(activity.activity.as_str()), Attribute::all())
+                    .build();
+                return Ok(object.into());
+            }
+        }
+        Err(JsNativeError::typ().with_message("'this' is not a Adb object").into())
+    }
+
+    /// `find_element(path)`: bounds of the first element matching an XPath-like path, or `null`.
+    pub fn find_element(this: &JsValue, args: &[JsValue], context: &mut Context<'_>) -> JsResult<JsValue> {
+        if let Some(object) = this.as_object() {
+            if let Some(mut adb) = object.downcast_mut::<Self>() {
+                let path = args.get_or_undefined(0).to_string(context)?.to_std_string_escaped();
+                let rect = adb
+                    .0
+                    .find_element(&autogui_core::Selector::Path(path))
+                    .map_err(|e| JsNativeError::typ().with_message(e.to_string()))?;
+                let Some(rect) = rect else {
+                    return Ok(JsValue::null());
+                };
+                let object = ObjectInitializer::new(context)
+                    .property(js_string!("left"), rect.left, Attribute::all())
+                    .property(js_string!("top"), rect.top, Attribute::all())
+                    .property(js_string!("right"), rect.right, Attribute::all())
+                    .property(js_string!("bottom"), rect.bottom, Attribute::all())
+                    .build();
+                return Ok(object.into());
+            }
+        }
+        Err(JsNativeError::typ().with_message("'this' is not a Adb object").into())
+    }
+
+    /// `wait_for_log(text, timeout_ms, tag?)`: the first new log entry containing `text`, or `null` on timeout.
+    pub fn wait_for_log(this: &JsValue, args: &[JsValue], context: &mut Context<'_>) -> JsResult<JsValue> {
+        if let Some(object) = this.as_object() {
+            if let Some(mut adb) = object.downcast_mut::<Self>() {
+                let text = args.get_or_undefined(0).to_string(context)?.to_std_string_escaped();
+                let timeout = Duration::from_millis(args.get_or_undefined(1).to_u32(context)? as u64);
+                let mut filter = autogui_core::LogcatFilter::new();
+                if !args.get_or_undefined(2).is_undefined() {
+                    filter = filter.with_tag(&args.get_or_undefined(2).to_string(context)?.to_std_string_escaped());
+                }
+                let entry = match adb.0.wait_for_log(filter, &text, timeout) {
+                    Ok(entry) => entry,
+                    Err(autogui_core::AGError::Timeout { .. }) => return Ok(JsValue::null()),
+                    Err(e) => return Err(JsNativeError::typ().with_message(e.to_string()).into()),
+                };
+                let object = ObjectInitializer::new(context)
+                    .property(js_string!("pid"), entry.pid, Attribute::all())
+                    .property(js_string!("tid"), entry.tid, Attribute::all())
+                    .property(js_string!("level"), JsString::from(entry.level.as_char().to_string().as_str()), Attribute::all())
+                    .property(js_string!("tag"), JsString::from(entry.tag.as_str()), Attribute::all())
+                    .property(js_string!("message"), JsString::from(entry.message.as_str()), Attribute::all())
+                    .build();
+                return Ok(object.into());
+            }
+        }
+        Err(JsNativeError::typ().with_message("'this' is not a Adb object").into())
+    }
+
+    /// `device_profile()`: SDK level, ABIs, model, density, screen size and emulator brand (or `null`).
+    pub fn device_profile(this: &JsValue, _args: &[JsValue], context: &mut Context<'_>) -> JsResult<JsValue> {
+        if let Some(object) = this.as_object() {
+            if let Some(mut adb) = object.downcast_mut::<Self>() {
+                let profile = adb.0.device_profile().map_err(|e| JsNativeError::typ().with_message(e.to_string()))?;
+                let abis = JsArray::from_iter(profile.abis.iter().map(|a| JsString::from(a.as_str()).into()), context);
+                let emulator: JsValue = match profile.emulator {
+                    Some(emulator) => JsString::from(emulator.name()).into(),
+                    None => JsValue::null(),
+                };
+                let object = ObjectInitializer::new(context)
+                    .property(js_string!("sdk"), profile.sdk, Attribute::all())
+                    .property(js_string!("release"), JsString::from(profile.release.as_str()), Attribute::all())
+                    .property(js_string!("abis"), abis, Attribute::all())
+                    .property(js_string!("manufacturer"), JsString::from(profile.manufacturer.as_str()), Attribute::all())
+                    .property(js_string!("model"), JsString::from(profile.model.as_str()), Attribute::all())
+                    .property(js_string!("density"), profile.density, Attribute::all())
+                    .property(js_string!("width"), profile.screen_size.0, Attribute::all())
+                    .property(js_string!("height"), profile.screen_size.1, Attribute::all())
+                    .property(js_string!("emulator"), emulator, Attribute::all())
+                    .build();
+                return Ok(object.into());
+            }
+        }
+        Err(JsNativeError::typ().with_message("'this' is not a Adb object").into())
+    }
 }
 
 impl Class for JsAdb {
@@ -68,7 +253,16 @@ impl Class for JsAdb {
 
     fn init(class: &mut ClassBuilder<'_, '_>) -> JsResult<()> {
         class.method("click", 2, NativeFunction::from_fn_ptr(Self::click));
+        class.method("swipe", 5, NativeFunction::from_fn_ptr(Self::swipe));
+        class.method("long_press", 3, NativeFunction::from_fn_ptr(Self::long_press));
+        class.method("drag", 6, NativeFunction::from_fn_ptr(Self::drag));
         class.method("screenshot", 0, NativeFunction::from_fn_ptr(Self::screenshot));
+        class.method("start_app", 2, NativeFunction::from_fn_ptr(Self::start_app));
+        class.method("stop_app", 1, NativeFunction::from_fn_ptr(Self::stop_app));
+        class.method("foreground", 0, NativeFunction::from_fn_ptr(Self::foreground));
+        class.method("find_element", 1, NativeFunction::from_fn_ptr(Self::find_element));
+        class.method("wait_for_log", 3, NativeFunction::from_fn_ptr(Self::wait_for_log));
+        class.method("device_profile", 0, NativeFunction::from_fn_ptr(Self::device_profile));
         Ok(())
     }
 }